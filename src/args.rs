@@ -1,3 +1,5 @@
+use crate::crds::key_algorithm::KeyAlgorithm;
+
 #[derive(clap::Args, Debug, Clone)]
 #[group(requires = "smtp-url", requires = "smtp-from-email")]
 pub struct SMTPArgs {
@@ -76,6 +78,20 @@ pub struct ServerArgs {
     pub port: u16,
 }
 
+/// Backend which signs user certificates.
+#[derive(clap::ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SignerBackend {
+    /// Create `CertificateSigningRequest` objects
+    /// and let kubernetes sign them.
+    #[default]
+    Kubernetes,
+    /// Sign certificates with the CA stored in a secret.
+    LocalCa,
+    /// Create cert-manager `CertificateRequest` objects
+    /// and let a cert-manager issuer sign them.
+    CertManager,
+}
+
 #[derive(clap::Parser, Debug, Clone)]
 #[clap(name = "kuo-operator", version, author, about)]
 pub struct OperatorArgs {
@@ -89,6 +105,54 @@ pub struct OperatorArgs {
     )]
     pub signer_name: String,
 
+    /// Backend which signs user certificates.
+    #[clap(
+        id = "signer-backend",
+        long = "signer-backend",
+        env = "KUO_OPERATOR_SIGNER_BACKEND",
+        default_value = "kubernetes"
+    )]
+    pub signer_backend: SignerBackend,
+
+    /// Name of the `kubernetes.io/tls` secret with the certificate authority
+    /// used by the `local-ca` signer backend.
+    /// The secret should be in the operator's namespace.
+    #[clap(
+        id = "ca-secret-name",
+        long = "ca-secret-name",
+        env = "KUO_OPERATOR_CA_SECRET_NAME",
+        default_value = "kuo-ca"
+    )]
+    pub ca_secret_name: String,
+
+    /// Name of the cert-manager issuer used by the `cert-manager` signer backend.
+    #[clap(
+        id = "cert-manager-issuer-name",
+        long = "cert-manager-issuer-name",
+        env = "KUO_OPERATOR_CERT_MANAGER_ISSUER_NAME",
+        default_value = "kuo"
+    )]
+    pub cert_manager_issuer_name: String,
+
+    /// Kind of the cert-manager issuer. Either `Issuer` or `ClusterIssuer`.
+    /// `Issuer` should be in the operator's namespace.
+    #[clap(
+        id = "cert-manager-issuer-kind",
+        long = "cert-manager-issuer-kind",
+        env = "KUO_OPERATOR_CERT_MANAGER_ISSUER_KIND",
+        default_value = "ClusterIssuer"
+    )]
+    pub cert_manager_issuer_kind: String,
+
+    /// API group of the cert-manager issuer.
+    #[clap(
+        id = "cert-manager-issuer-group",
+        long = "cert-manager-issuer-group",
+        env = "KUO_OPERATOR_CERT_MANAGER_ISSUER_GROUP",
+        default_value = "cert-manager.io"
+    )]
+    pub cert_manager_issuer_group: String,
+
     /// Kubernetes API server host.
     #[clap(
         id = "kube-addr",
@@ -117,6 +181,93 @@ pub struct OperatorArgs {
     )]
     pub default_cert_key: String,
 
+    /// Default algorithm of private keys generated for users.
+    /// Can be overridden for a specific user in the `ManagedUser` spec.
+    #[clap(
+        id = "key-algorithm",
+        long = "key-algorithm",
+        env = "KUO_OPERATOR_KEY_ALGORITHM",
+        default_value = "rsa4096"
+    )]
+    pub key_algorithm: KeyAlgorithm,
+
+    /// Default lifetime of issued client certificates in seconds.
+    /// Can be overridden for a specific user in the `ManagedUser` spec.
+    /// Signers may issue certificates with a shorter lifetime.
+    #[clap(
+        id = "cert-expiration-seconds",
+        long = "cert-expiration-seconds",
+        env = "KUO_OPERATOR_CERT_EXPIRATION_SECONDS",
+        default_value = "31536000",
+        value_parser = clap::value_parser!(i32).range(600..),
+    )]
+    pub cert_expiration_seconds: i32,
+
+    /// Percentage of the certificate lifetime before its expiry
+    /// at which the operator requests a new certificate for the user.
+    #[clap(
+        id = "cert-renew-before-percent",
+        long = "cert-renew-before-percent",
+        env = "KUO_OPERATOR_CERT_RENEW_BEFORE_PERCENT",
+        default_value = "20",
+        value_parser = clap::value_parser!(u8).range(1..100),
+    )]
+    pub cert_renew_before_percent: u8,
+
+    /// Suffix the user's identity in certificates with the credential generation,
+    /// e.g. `alice#1750000000`. Only the current generation is bound to the user's roles,
+    /// so rotation revokes the permissions of older certificates.
+    #[clap(
+        id = "identity-generations",
+        long = "identity-generations",
+        env = "KUO_OPERATOR_IDENTITY_GENERATIONS",
+        default_value_t = false
+    )]
+    pub identity_generations: bool,
+
+    /// Take over fields owned by other field managers when applying objects
+    /// which haven't been created by the operator.
+    /// Otherwise such conflicts are reported as reconciliation errors.
+    #[clap(
+        id = "force-conflicts",
+        long = "force-conflicts",
+        env = "KUO_OPERATOR_FORCE_CONFLICTS",
+        default_value_t = false
+    )]
+    pub force_conflicts: bool,
+
+    /// Groups users are allowed to be members of.
+    /// If empty, all groups except the reserved `system:` ones are allowed.
+    #[clap(
+        id = "allowed-groups",
+        long = "allowed-groups",
+        env = "KUO_OPERATOR_ALLOWED_GROUPS",
+        value_delimiter = ','
+    )]
+    pub allowed_groups: Vec<String>,
+
+    /// Number of approvals required to grant an access request.
+    /// Approvers are only verified by the admission policy of the helm chart.
+    #[clap(
+        id = "access-request-approvals",
+        long = "access-request-approvals",
+        env = "KUO_OPERATOR_ACCESS_REQUEST_APPROVALS",
+        default_value = "1",
+        value_parser = clap::value_parser!(u8).range(1..)
+    )]
+    pub access_request_approvals: u8,
+
+    /// Maximum duration of access requests in seconds.
+    /// Requests for a longer access are rejected.
+    #[clap(
+        id = "access-request-max-duration",
+        long = "access-request-max-duration",
+        env = "KUO_OPERATOR_ACCESS_REQUEST_MAX_DURATION",
+        default_value = "86400",
+        value_parser = clap::value_parser!(i64).range(1..)
+    )]
+    pub access_request_max_duration: i64,
+
     #[clap(
         id = "cluster-name",
         long = "cluster-name",
@@ -39,7 +39,11 @@ pub fn main() -> KuoResult<()> {
     }
     dotenvy::dotenv().ok();
     let args = CrdsArgs::parse();
-    let defs = generate_crds_def(vec![kuo::crds::managed_user::ManagedUser::crd()])?;
+    let defs = generate_crds_def(vec![
+        kuo::crds::managed_user::ManagedUser::crd(),
+        kuo::crds::access_request::AccessRequest::crd(),
+        kuo::crds::managed_group::ManagedGroup::crd(),
+    ])?;
     if let Some(out_file) = args.out_file {
         let output = OpenOptions::new()
             .write(true)
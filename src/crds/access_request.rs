@@ -0,0 +1,268 @@
+use std::{collections::BTreeMap, sync::Arc};
+
+use k8s_openapi::api::core::v1::Namespace;
+use kube::{api::ObjectMeta, CustomResource, ResourceExt};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::operator::{
+    ctx::OperatorCtx,
+    error::KuoResult,
+    utils::{meta::ObjectMetaKuoExt, rbac},
+};
+
+use super::{
+    inline_permissions::{AppliedPermissions, NamespacedPermissions, Permission},
+    managed_user::{immutable_rule, ManagedUser},
+};
+
+/// Label set on roles and role bindings created for an access request.
+pub const ACCESS_REQUEST_LABEL: &str = "kuo.github.io/access-request";
+
+/// Label with the name of the user who requested the access.
+pub const ACCESS_REQUEST_USER_LABEL: &str = "kuo.github.io/access-request-user";
+
+#[derive(CustomResource, Debug, Serialize, Deserialize, Default, Clone, JsonSchema)]
+#[kube(
+    group = "kuo.github.io",
+    version = "v1",
+    kind = "AccessRequest",
+    status = "AccessRequestStatus",
+    printcolumn = r#"
+    {
+        "name": "User",
+        "type": "string",
+        "description": "User who requests access",
+        "jsonPath": ".spec.user"
+    },
+    {
+        "name": "Phase",
+        "type": "string",
+        "description": "Phase of the request",
+        "jsonPath": ".status.phase"
+    },
+    {
+        "name": "Expires",
+        "type": "string",
+        "description": "Time when the access expires",
+        "jsonPath": ".status.expiresAt"
+    }
+    "#
+)]
+#[serde(rename_all = "camelCase")]
+pub struct AccessRequestCRD {
+    /// Name of the `ManagedUser` who requests access.
+    #[schemars(schema_with = "immutable_rule::<String>")]
+    pub user: String,
+    /// Why the access is needed.
+    #[schemars(schema_with = "immutable_rule::<String>")]
+    pub justification: String,
+    /// For how long the access is granted after the approval, in seconds.
+    /// Must be positive and not exceed the operator's `--access-request-max-duration`.
+    #[schemars(schema_with = "immutable_rule::<i64>")]
+    pub duration_seconds: i64,
+    /// List of requested cluster-wide permissions.
+    #[schemars(schema_with = "immutable_rule::<Vec<Permission>>")]
+    #[serde(default)]
+    pub cluster_permissions: Vec<Permission>,
+    /// List of requested namespaced permissions.
+    #[schemars(schema_with = "immutable_rule::<Vec<NamespacedPermissions>>")]
+    #[serde(default)]
+    pub namespaced_permissions: Vec<NamespacedPermissions>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Default, Debug, PartialEq, Eq, JsonSchema)]
+pub enum AccessRequestPhase {
+    /// Request is waiting for approvals.
+    #[default]
+    Pending,
+    /// Access is granted.
+    Granted,
+    /// Access has expired.
+    Expired,
+    /// Request is invalid and will never be granted.
+    Rejected,
+}
+
+/// Approval of the access request.
+///
+/// Approvals are added to the status by the approvers,
+/// the operator never modifies them.
+#[derive(Deserialize, Serialize, Clone, Default, Debug, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Approval {
+    /// Time of the approval in RFC 3339 format.
+    #[serde(default)]
+    pub approved_at: Option<String>,
+    /// Comment of the approver.
+    #[serde(default)]
+    pub comment: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Default, Debug, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct AccessRequestStatus {
+    #[serde(default)]
+    pub phase: AccessRequestPhase,
+    /// Human-readable details about the phase.
+    #[serde(default)]
+    pub message: Option<String>,
+    /// Approvals of the request by the names of the approvers.
+    ///
+    /// Only those allowed to update the `accessrequests/status` subresource can approve requests.
+    /// The admission policy of the chart makes sure that approvers only add approvals
+    /// under their own usernames.
+    /// The field is left out of the operator's status patches, so it never overwrites approvals.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub approvals: BTreeMap<String, Approval>,
+    /// Time when the access was granted in RFC 3339 format.
+    #[serde(default)]
+    pub granted_at: Option<String>,
+    /// Time when the access expires in RFC 3339 format.
+    #[serde(default)]
+    pub expires_at: Option<String>,
+    /// Roles created for the request in the `{namespace}/{name}` format.
+    #[serde(default)]
+    pub roles: Vec<String>,
+    /// Cluster roles created for the request.
+    #[serde(default)]
+    pub cluster_roles: Vec<String>,
+    /// Namespaces which don't exist yet.
+    /// Permissions are applied as soon as they are created.
+    #[serde(default)]
+    pub pending_namespaces: Vec<String>,
+    /// Namespaces where permissions could not be applied.
+    #[serde(default)]
+    pub failed_namespaces: Vec<String>,
+}
+
+impl AccessRequest {
+    /// Approvals counted towards the request.
+    ///
+    /// The requesting user cannot approve their own request,
+    /// regardless of the credential generation of their identity.
+    #[must_use]
+    pub fn approvals(&self) -> Vec<(&String, &Approval)> {
+        self.status
+            .iter()
+            .flat_map(|status| &status.approvals)
+            .filter(|(approver, _)| !approver.is_empty() && !self.is_requester(approver))
+            .collect()
+    }
+
+    /// Check whether the username belongs to the requesting user.
+    fn is_requester(&self, username: &str) -> bool {
+        username
+            .strip_prefix(self.spec.user.as_str())
+            .is_some_and(|generation| generation.is_empty() || generation.starts_with('#'))
+    }
+
+    /// Check whether a change of the namespace affects the request's roles.
+    #[must_use]
+    pub fn affected_by_namespace(&self, namespace: &Namespace) -> bool {
+        let roles = self
+            .status
+            .as_ref()
+            .map(|status| status.roles.as_slice())
+            .unwrap_or_default();
+        rbac::namespace_affects(namespace, &self.spec.namespaced_permissions, roles)
+    }
+
+    /// Create roles and role bindings with the requested permissions for the user.
+    pub async fn grant(
+        &self,
+        user: &ManagedUser,
+        ctx: Arc<OperatorCtx>,
+    ) -> KuoResult<AppliedPermissions> {
+        let mut metadata = ObjectMeta {
+            name: Some(format!("kuo-access-{}", self.name_any())),
+            ..Default::default()
+        };
+        metadata.add_owner(self);
+        metadata.insert_label(ACCESS_REQUEST_LABEL, self.name_any());
+        metadata.insert_label(ACCESS_REQUEST_USER_LABEL, &self.spec.user);
+        rbac::apply_permissions(
+            &ctx,
+            &metadata,
+            (ACCESS_REQUEST_LABEL, &self.name_any()),
+            &self.spec.namespaced_permissions,
+            &self.spec.cluster_permissions,
+            &user.rbac_subjects(ctx.args.identity_generations),
+        )
+        .await
+    }
+
+    /// Remove all roles and role bindings created for the request.
+    pub async fn revoke(&self, ctx: Arc<OperatorCtx>) -> KuoResult<()> {
+        rbac::delete_labelled(&ctx, (ACCESS_REQUEST_LABEL, &self.name_any())).await
+    }
+
+    /// Remove roles and role bindings of all access requests of the user.
+    pub async fn revoke_all_for_user(user: &ManagedUser, ctx: Arc<OperatorCtx>) -> KuoResult<()> {
+        rbac::delete_labelled(&ctx, (ACCESS_REQUEST_USER_LABEL, &user.name_any())).await
+    }
+
+    /// Reason why the request can never be granted, if it's invalid.
+    #[must_use]
+    pub fn rejection(&self, max_duration: i64) -> Option<String> {
+        if !(1..=max_duration).contains(&self.spec.duration_seconds) {
+            return Some(format!(
+                "Duration must be between 1 and {max_duration} seconds."
+            ));
+        }
+        if !self
+            .spec
+            .namespaced_permissions
+            .iter()
+            .all(NamespacedPermissions::is_valid)
+        {
+            return Some(String::from(
+                "Namespaced permissions need a namespace, a namespaceSelector or a namespacePattern.",
+            ));
+        }
+        None
+    }
+
+    /// Status of the request, created if the request has none yet.
+    pub fn status_or_default(&mut self) -> &mut AccessRequestStatus {
+        self.status.get_or_insert_with(AccessRequestStatus::default)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(approvers: &[&str]) -> AccessRequest {
+        let mut request = AccessRequest::new(
+            "debug",
+            AccessRequestCRD {
+                user: String::from("alice"),
+                ..AccessRequestCRD::default()
+            },
+        );
+        let status = request.status_or_default();
+        for approver in approvers {
+            status
+                .approvals
+                .insert(String::from(*approver), Approval::default());
+        }
+        request
+    }
+
+    #[test]
+    fn approvals_without_status() {
+        assert!(request(&[]).approvals().is_empty());
+    }
+
+    #[test]
+    fn approvals_ignore_requester() {
+        let request = request(&["alice", "alice#2", "alice2", "bob", ""]);
+        let approvers = request
+            .approvals()
+            .into_iter()
+            .map(|(approver, _)| approver.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(approvers, ["alice2", "bob"]);
+    }
+}
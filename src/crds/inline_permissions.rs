@@ -1,24 +1,30 @@
 use std::{
-    collections::HashSet,
-    hash::{DefaultHasher, Hash, Hasher},
+    collections::{BTreeMap, HashSet},
     sync::Arc,
 };
 
 use k8s_openapi::{
-    api::rbac::v1::{ClusterRole, ClusterRoleBinding, PolicyRule, Role, RoleBinding},
+    api::{
+        core::v1::Namespace,
+        rbac::v1::{ClusterRole, ClusterRoleBinding, PolicyRule, Role, RoleBinding},
+    },
     Resource,
 };
 use kube::{
-    api::{DeleteParams, ListParams, ObjectMeta},
+    api::{ObjectMeta, PostParams},
+    runtime::reflector::Store,
     ResourceExt,
 };
-use schemars::JsonSchema;
+use schemars::{schema::SchemaObject, JsonSchema};
 use serde::{Deserialize, Serialize};
 
 use crate::operator::{
+    cache::{labelled, MANAGED_BY, MANAGED_BY_LABEL},
     ctx::OperatorCtx,
     error::KuoResult,
-    utils::{meta::ObjectMetaKuoExt, resource::KuoResourceExt},
+    utils::{
+        digest::digest, glob::glob_match, meta::ObjectMetaKuoExt, rbac, resource::KuoResourceExt,
+    },
 };
 
 use super::managed_user::ManagedUser;
@@ -44,20 +50,85 @@ pub struct Permission {
 }
 
 #[derive(Debug, Serialize, Deserialize, Default, Clone, JsonSchema)]
+#[serde(rename_all = "camelCase")]
 pub struct NamespacedPermissions {
     // Name of the namespace to apply permission to.
+    // Can be omitted if namespaces are matched by a selector or a pattern.
+    #[serde(default)]
     pub namespace: String,
+    /// Apply permissions to namespaces with matching labels.
+    #[serde(default)]
+    pub namespace_selector: Option<NamespaceSelector>,
+    /// Apply permissions to namespaces with names matching the glob, e.g. `team-a-*`.
+    #[serde(default)]
+    pub namespace_pattern: Option<String>,
+    /// Namespaces to skip even if they match the selector or the pattern.
+    #[serde(default)]
+    pub exclude_namespaces: Option<Vec<String>>,
+    /// Create the namespace if it doesn't exist.
+    /// The namespace is kept when the permissions are removed.
+    #[serde(default)]
+    pub create_namespace: Option<bool>,
     // List of permissions to apply to the namespace.
     pub permissions: Vec<Permission>,
 }
 
+#[derive(Debug, Serialize, Deserialize, Default, Clone, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct NamespaceSelector {
+    /// Labels which the namespace must have.
+    #[serde(default)]
+    pub match_labels: BTreeMap<String, String>,
+}
+
 #[derive(Debug, Serialize, Deserialize, Default, Clone, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct InlinePermissions {
     /// List of cluster-wide permissions.
     pub cluster_permissions: Option<Vec<Permission>>,
     /// List of namespaced permissions.
+    #[schemars(schema_with = "namespaced_permissions_rule")]
     pub namespaced_permissions: Option<Vec<NamespacedPermissions>>,
+    /// Bind permissions to the user's groups as well as to the user.
+    pub bind_groups: Option<bool>,
+}
+
+/// Result of applying inline permissions.
+#[derive(Debug, Default, Clone)]
+pub struct AppliedPermissions {
+    /// Roles created for the user in the `{namespace}/{name}` format.
+    pub roles: Vec<String>,
+    /// Cluster roles created for the user.
+    pub cluster_roles: Vec<String>,
+    /// Role bindings to referenced roles in the `{namespace}/{name}` format.
+    pub role_bindings: Vec<String>,
+    /// Cluster role bindings to referenced cluster roles.
+    pub cluster_role_bindings: Vec<String>,
+    /// Namespaces where permissions could not be applied.
+    pub failed_namespaces: Vec<String>,
+    /// Namespaces which don't exist yet.
+    /// Permissions are applied as soon as they are created.
+    pub pending_namespaces: Vec<String>,
+    /// Objects whose manual changes have been reverted.
+    pub reverted: Vec<String>,
+}
+
+/// Require a namespace, a selector or a pattern in every namespaced permission.
+pub fn namespaced_permissions_rule(
+    gen: &mut schemars::gen::SchemaGenerator,
+) -> schemars::schema::Schema {
+    let mut schema: SchemaObject = <Option<Vec<NamespacedPermissions>>>::json_schema(gen).into();
+    schema.extensions.insert(
+        String::from("x-kubernetes-validations"),
+        serde_json::json!([
+            {
+                "rule": "self.all(p, (has(p.namespace) && p.namespace != '') \
+                         || has(p.namespaceSelector) || has(p.namespacePattern))",
+                "message": "Namespaced permissions need a namespace, a namespaceSelector or a namespacePattern."
+            }
+        ]),
+    );
+    schema.into()
 }
 
 impl From<Permission> for PolicyRule {
@@ -72,16 +143,160 @@ impl From<Permission> for PolicyRule {
     }
 }
 
+/// Get the name of the user's role with the given digest of rules.
+///
+/// Existing roles labelled with the digest are reused, so roles adopted
+/// by [`crate::operator::migrations`] keep their names.
+/// New roles are named `{user}-{digest}`.
+fn role_name<K>(
+    user: &ManagedUser,
+    cache: &Store<K>,
+    namespace: Option<&str>,
+    digest: &str,
+) -> String
+where
+    K: kube::Resource<DynamicType = ()> + Clone + 'static,
+{
+    labelled(cache, (rbac::DIGEST_LABEL, digest))
+        .into_iter()
+        .find(|role| {
+            role.labels().get("kuo.github.com/user") == Some(&user.name_any())
+                && role.namespace().as_deref() == namespace
+        })
+        .map_or_else(
+            || format!("{}-{digest}", user.name_any()),
+            |role| role.name_any(),
+        )
+}
+
 impl NamespacedPermissions {
-    pub async fn apply(&self, user: &ManagedUser, ctx: Arc<OperatorCtx>) -> KuoResult<String> {
-        let mut hasher = DefaultHasher::new();
-        serde_yaml::to_string(self)?.hash(&mut hasher);
-        let name = format!("{}-{}", user.name_any(), hasher.finish());
-        // api.get_metadata_opt(name)
+    /// Check whether namespaces are matched by a selector or a pattern
+    /// rather than by name.
+    #[must_use]
+    pub const fn is_dynamic(&self) -> bool {
+        self.namespace_selector.is_some() || self.namespace_pattern.is_some()
+    }
+
+    /// Check whether the permissions have a namespace, a selector or a pattern.
+    /// Otherwise they don't apply to any namespace.
+    #[must_use]
+    pub const fn is_valid(&self) -> bool {
+        !self.namespace.is_empty() || self.is_dynamic()
+    }
+
+    /// Check whether the permissions apply to the namespace.
+    ///
+    /// The namespace must either have the configured name
+    /// or match both the selector and the pattern, if they are set.
+    #[must_use]
+    pub fn matches(&self, namespace: &Namespace) -> bool {
+        let name = namespace.name_any();
+        if self
+            .exclude_namespaces
+            .as_ref()
+            .is_some_and(|excluded| excluded.contains(&name))
+        {
+            return false;
+        }
+        if !self.namespace.is_empty() && name == self.namespace {
+            return true;
+        }
+        // Selector and pattern are optional, but both must match if set.
+        let unselected = self.namespace_selector.as_ref().is_some_and(|selector| {
+            !selector
+                .match_labels
+                .iter()
+                .all(|(key, value)| namespace.labels().get(key) == Some(value))
+        });
+        let unmatched = self
+            .namespace_pattern
+            .as_ref()
+            .is_some_and(|pattern| !glob_match(pattern, &name));
+        self.is_dynamic() && !unselected && !unmatched
+    }
+
+    /// Create the namespace if `createNamespace` is set and it doesn't exist yet.
+    async fn ensure_namespace(&self, client: &kube::Client) -> KuoResult<()> {
+        if self.create_namespace != Some(true) || self.namespace.is_empty() {
+            return Ok(());
+        }
+        if kube::Api::<Namespace>::all(client.clone())
+            .get_metadata_opt(&self.namespace)
+            .await?
+            .is_some()
+        {
+            return Ok(());
+        }
+        tracing::info!("Creating namespace {}", self.namespace);
+        let mut metadata = ObjectMeta {
+            name: Some(self.namespace.clone()),
+            ..Default::default()
+        };
+        metadata.insert_label("app.kubernetes.io/managed-by", "kuo-operator");
+        kube::Api::<Namespace>::all(client.clone())
+            .create(
+                &PostParams::default(),
+                &Namespace {
+                    metadata,
+                    ..Default::default()
+                },
+            )
+            .await?;
+        Ok(())
+    }
+
+    /// Get names of the namespaces the permissions apply to.
+    ///
+    /// Namespaces matched by a selector or a pattern are looked up in the cache.
+    /// Namespaces which are being deleted are skipped.
+    /// The namespace is created first if `createNamespace` is set.
+    pub async fn namespaces(
+        &self,
+        client: &kube::Client,
+        cache: &Store<Namespace>,
+    ) -> KuoResult<Vec<String>> {
+        if !self.is_valid() {
+            tracing::warn!("Namespaced permissions have no namespace, selector or pattern");
+            return Ok(vec![]);
+        }
+        self.ensure_namespace(client).await?;
+        if !self.is_dynamic() {
+            return Ok(vec![self.namespace.clone()]);
+        }
+        Ok(cache
+            .state()
+            .into_iter()
+            .filter(|namespace| {
+                let terminating = namespace
+                    .status
+                    .as_ref()
+                    .and_then(|status| status.phase.as_deref())
+                    == Some("Terminating");
+                !terminating && self.matches(namespace)
+            })
+            .map(|namespace| namespace.name_any())
+            .collect())
+    }
+
+    /// Apply the permissions in the namespace.
+    ///
+    /// Objects whose manual changes have been reverted are added to `reverted`.
+    pub async fn apply(
+        &self,
+        user: &ManagedUser,
+        namespace: &str,
+        ctx: Arc<OperatorCtx>,
+        reverted: &mut Vec<String>,
+    ) -> KuoResult<String> {
+        let digest = digest(&self.permissions)?;
+        let name = role_name(user, &ctx.cache.roles, Some(namespace), &digest);
         let mut role_metadata = ObjectMeta::default();
         role_metadata.add_owner(user);
         role_metadata.name = Some(name.clone());
+        role_metadata.namespace = Some(String::from(namespace));
         role_metadata.insert_label("kuo.github.com/user", user.name_any());
+        role_metadata.insert_label(rbac::DIGEST_LABEL, &digest);
+        role_metadata.insert_label(MANAGED_BY_LABEL, MANAGED_BY);
         let mut new_role = Role {
             metadata: role_metadata,
             rules: Some(
@@ -93,13 +308,19 @@ impl NamespacedPermissions {
             ),
         };
         new_role = new_role
-            .patch_or_create(kube::Api::namespaced(ctx.client.clone(), &self.namespace))
-            .await?;
+            .patch_or_create_cached(
+                kube::Api::namespaced(ctx.client.clone(), namespace),
+                &ctx.cache.roles,
+                ctx.args.force_conflicts,
+            )
+            .await?
+            .record(reverted);
         let mut rb_metadata = ObjectMeta::default();
         rb_metadata.add_owner(&new_role);
         rb_metadata.name = Some(name.clone());
         rb_metadata.insert_label("kuo.github.com/user", user.name_any());
-        rb_metadata.namespace = Some(self.namespace.clone());
+        rb_metadata.insert_label(MANAGED_BY_LABEL, MANAGED_BY);
+        rb_metadata.namespace = Some(String::from(namespace));
         let role_binding = RoleBinding {
             metadata: rb_metadata,
             role_ref: k8s_openapi::api::rbac::v1::RoleRef {
@@ -107,16 +328,16 @@ impl NamespacedPermissions {
                 kind: String::from(Role::KIND),
                 name: name.clone(),
             },
-            subjects: Some(vec![k8s_openapi::api::rbac::v1::Subject {
-                kind: "User".to_string(),
-                name: user.name_any(),
-                namespace: None,
-                api_group: None,
-            }]),
+            subjects: Some(user.rbac_subjects(ctx.args.identity_generations)),
         };
         role_binding
-            .patch_or_create(kube::Api::namespaced(ctx.client.clone(), &self.namespace))
-            .await?;
+            .patch_or_create_cached(
+                kube::Api::namespaced(ctx.client.clone(), namespace),
+                &ctx.cache.role_bindings,
+                ctx.args.force_conflicts,
+            )
+            .await?
+            .record(reverted);
         Ok(name)
     }
 }
@@ -124,31 +345,27 @@ impl NamespacedPermissions {
 impl InlinePermissions {
     /// This function will remove all roles that are not in the `known_permissions` set.
     ///
-    /// It iterates over all roles in the cluster and deletes the ones that are not in the `known_permissions` set,
+    /// It iterates over all cached roles and deletes the ones that are not in the `known_permissions` set,
     /// and have the label `kuo.github.com/user` set to the username.
+    /// Known roles are identified as `{namespace}/{name}`.
     #[allow(clippy::missing_panics_doc)]
     pub async fn remove_unknown_namespaced_roles(
         user: &ManagedUser,
         known_permissions: &HashSet<String>,
         ctx: Arc<OperatorCtx>,
     ) -> KuoResult<()> {
-        let roles = kube::Api::<Role>::all(ctx.client.clone())
-            .list(&ListParams {
-                label_selector: Some(format!("kuo.github.com/user={}", user.name_any())),
-                ..Default::default()
-            })
-            .await?;
+        let roles = labelled(&ctx.cache.roles, ("kuo.github.com/user", &user.name_any()));
         for role in roles {
-            if known_permissions.contains(role.name_any().as_str()) {
+            // SAFETY: We are sure that the namespace is set,
+            // because we are listing roles, which are always namespaced.
+            let namespace = role.namespace().unwrap();
+            if known_permissions.contains(&format!("{namespace}/{}", role.name_any())) {
                 continue;
             }
-            kube::Api::<Role>::namespaced(
-                ctx.client.clone(),
-                // SAFETY: We are sure that the namespace is set,
-                // because we are listing roles, which are always namespaced.
-                role.namespace().as_deref().unwrap(),
+            rbac::delete_if_exists(
+                kube::Api::<Role>::namespaced(ctx.client.clone(), &namespace),
+                &role.name_any(),
             )
-            .delete(role.name_any().as_str(), &DeleteParams::default())
             .await?;
         }
         Ok(())
@@ -164,23 +381,40 @@ impl InlinePermissions {
         &self,
         user: &ManagedUser,
         ctx: Arc<OperatorCtx>,
+        applied: &mut AppliedPermissions,
     ) -> KuoResult<()> {
         let mut known_permissions = HashSet::new();
         if let Some(namespaced_permissions) = &self.namespaced_permissions {
             for namespaced in namespaced_permissions {
-                let res = namespaced.apply(user, ctx.clone()).await;
-                match res {
-                    Ok(name) => {
-                        known_permissions.insert(name);
-                    }
-                    Err(err) => {
-                        tracing::warn!("Failed to create namespaced permission. {err}");
+                for namespace in namespaced
+                    .namespaces(&ctx.client, &ctx.cache.namespaces)
+                    .await?
+                {
+                    let res = namespaced
+                        .apply(user, &namespace, ctx.clone(), &mut applied.reverted)
+                        .await;
+                    match res {
+                        Ok(name) => {
+                            let role = format!("{namespace}/{name}");
+                            applied.roles.push(role.clone());
+                            known_permissions.insert(role);
+                        }
+                        Err(err) if err.is_not_found() => {
+                            tracing::info!(
+                                "Namespace {namespace} doesn't exist yet. \
+                                 Permissions will be applied once it's created."
+                            );
+                            applied.pending_namespaces.push(namespace);
+                        }
+                        Err(err) => {
+                            tracing::warn!("Failed to create namespaced permission. {err}");
+                            applied.failed_namespaces.push(namespace);
+                        }
                     }
                 }
             }
         }
-        Self::remove_unknown_namespaced_roles(user, &known_permissions, ctx.clone()).await?;
-        Ok(())
+        Self::remove_unknown_namespaced_roles(user, &known_permissions, ctx.clone()).await
     }
 
     async fn remove_unknown_cluster_roles(
@@ -188,21 +422,21 @@ impl InlinePermissions {
         known_permission: Option<String>,
         ctx: Arc<OperatorCtx>,
     ) -> KuoResult<()> {
-        let roles = kube::Api::<ClusterRole>::all(ctx.client.clone())
-            .list(&ListParams {
-                label_selector: Some(format!("kuo.github.com/user={}", user.name_any())),
-                ..Default::default()
-            })
-            .await?;
+        let roles = labelled(
+            &ctx.cache.cluster_roles,
+            ("kuo.github.com/user", &user.name_any()),
+        );
         for role in roles {
             if let Some(known_name) = &known_permission {
                 if role.name_any() == *known_name {
                     continue;
                 }
             }
-            kube::Api::<ClusterRole>::all(ctx.client.clone())
-                .delete(role.name_any().as_str(), &DeleteParams::default())
-                .await?;
+            rbac::delete_if_exists(
+                kube::Api::<ClusterRole>::all(ctx.client.clone()),
+                &role.name_any(),
+            )
+            .await?;
         }
         Ok(())
     }
@@ -211,18 +445,19 @@ impl InlinePermissions {
         &self,
         user: &ManagedUser,
         ctx: Arc<OperatorCtx>,
+        applied: &mut AppliedPermissions,
     ) -> KuoResult<()> {
         let mut known_name = None;
         if let Some(namespaced_permissions) = &self.cluster_permissions {
-            let mut hasher = DefaultHasher::new();
-            serde_yaml::to_string(namespaced_permissions)?.hash(&mut hasher);
-            let name = format!("{}-{}", user.name_any(), hasher.finish());
+            let digest = digest(namespaced_permissions)?;
+            let name = role_name(user, &ctx.cache.cluster_roles, None, &digest);
             known_name = Some(name.clone());
-            // api.get_metadata_opt(name)
             let mut role_metadata = ObjectMeta::default();
             role_metadata.add_owner(user);
             role_metadata.name = Some(name.clone());
             role_metadata.insert_label("kuo.github.com/user", user.name_any());
+            role_metadata.insert_label(rbac::DIGEST_LABEL, &digest);
+            role_metadata.insert_label(MANAGED_BY_LABEL, MANAGED_BY);
             let mut new_role = ClusterRole {
                 metadata: role_metadata,
                 rules: Some(
@@ -235,12 +470,18 @@ impl InlinePermissions {
                 ..Default::default()
             };
             new_role = new_role
-                .patch_or_create(kube::Api::all(ctx.client.clone()))
-                .await?;
+                .patch_or_create_cached(
+                    kube::Api::all(ctx.client.clone()),
+                    &ctx.cache.cluster_roles,
+                    ctx.args.force_conflicts,
+                )
+                .await?
+                .record(&mut applied.reverted);
             let mut rb_metadata = ObjectMeta::default();
             rb_metadata.add_owner(&new_role);
             rb_metadata.name = Some(name.clone());
             rb_metadata.insert_label("kuo.github.com/user", user.name_any());
+            rb_metadata.insert_label(MANAGED_BY_LABEL, MANAGED_BY);
             let role_binding = ClusterRoleBinding {
                 metadata: rb_metadata,
                 role_ref: k8s_openapi::api::rbac::v1::RoleRef {
@@ -248,28 +489,136 @@ impl InlinePermissions {
                     kind: String::from(ClusterRole::KIND),
                     name: name.clone(),
                 },
-                subjects: Some(vec![k8s_openapi::api::rbac::v1::Subject {
-                    kind: "User".to_string(),
-                    name: user.name_any(),
-                    namespace: None,
-                    api_group: None,
-                }]),
+                subjects: Some(user.rbac_subjects(ctx.args.identity_generations)),
             };
             role_binding
-                .patch_or_create(kube::Api::all(ctx.client.clone()))
-                .await?;
+                .patch_or_create_cached(
+                    kube::Api::all(ctx.client.clone()),
+                    &ctx.cache.cluster_role_bindings,
+                    ctx.args.force_conflicts,
+                )
+                .await?
+                .record(&mut applied.reverted);
+            applied.cluster_roles.push(name);
         }
-        Self::remove_unknown_cluster_roles(user, known_name, ctx.clone()).await?;
-        Ok(())
+        Self::remove_unknown_cluster_roles(user, known_name, ctx.clone()).await
+    }
+
+    /// Remove all roles and role bindings created for the user.
+    pub async fn remove_all(user: &ManagedUser, ctx: Arc<OperatorCtx>) -> KuoResult<()> {
+        rbac::delete_labelled(&ctx, ("kuo.github.com/user", &user.name_any())).await
     }
 
     /// Apply inlined permissions for a user.
     ///
     /// This function will sync all permissions for the user.
     /// But it won't delete any permissions that were not created by this operator.
-    pub async fn apply(&self, user: &ManagedUser, ctx: Arc<OperatorCtx>) -> KuoResult<()> {
-        self.apply_namespaced_permissions(user, ctx.clone()).await?;
-        self.apply_cluster_permissions(user, ctx).await?;
-        Ok(())
+    pub async fn apply(
+        &self,
+        user: &ManagedUser,
+        ctx: Arc<OperatorCtx>,
+    ) -> KuoResult<AppliedPermissions> {
+        let mut applied = AppliedPermissions::default();
+        self.apply_namespaced_permissions(user, ctx.clone(), &mut applied)
+            .await?;
+        self.apply_cluster_permissions(user, ctx, &mut applied)
+            .await?;
+        Ok(applied)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn namespace(name: &str, labels: &[(&str, &str)]) -> Namespace {
+        Namespace {
+            metadata: ObjectMeta {
+                name: Some(String::from(name)),
+                labels: Some(
+                    labels
+                        .iter()
+                        .map(|(key, value)| (String::from(*key), String::from(*value)))
+                        .collect(),
+                ),
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    fn selector(labels: &[(&str, &str)]) -> NamespaceSelector {
+        NamespaceSelector {
+            match_labels: labels
+                .iter()
+                .map(|(key, value)| (String::from(*key), String::from(*value)))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn matches_table() {
+        let by_name = NamespacedPermissions {
+            namespace: String::from("default"),
+            ..Default::default()
+        };
+        let by_pattern = NamespacedPermissions {
+            namespace_pattern: Some(String::from("team-a-*")),
+            exclude_namespaces: Some(vec![String::from("team-a-secrets")]),
+            ..Default::default()
+        };
+        let by_selector = NamespacedPermissions {
+            namespace_selector: Some(selector(&[("env", "dev")])),
+            ..Default::default()
+        };
+        let by_both = NamespacedPermissions {
+            namespace_pattern: Some(String::from("team-a-*")),
+            namespace_selector: Some(selector(&[("env", "dev")])),
+            ..Default::default()
+        };
+        let empty = NamespacedPermissions::default();
+        let dev = [("env", "dev")];
+        let cases = [
+            (&by_name, namespace("default", &[]), true),
+            (&by_name, namespace("kube-system", &[]), false),
+            (&by_pattern, namespace("team-a-dev", &[]), true),
+            (&by_pattern, namespace("team-b-dev", &[]), false),
+            (&by_pattern, namespace("team-a-secrets", &[]), false),
+            (&by_selector, namespace("anything", &dev), true),
+            (
+                &by_selector,
+                namespace("anything", &[("env", "prod")]),
+                false,
+            ),
+            (&by_selector, namespace("anything", &[]), false),
+            (&by_both, namespace("team-a-dev", &dev), true),
+            (&by_both, namespace("team-a-dev", &[]), false),
+            (&by_both, namespace("team-b-dev", &dev), false),
+            (&empty, namespace("default", &[]), false),
+            (&empty, namespace("", &[]), false),
+        ];
+        for (permissions, namespace, expected) in cases {
+            assert_eq!(
+                permissions.matches(&namespace),
+                expected,
+                "{permissions:?} matching {}",
+                namespace.name_any()
+            );
+        }
+    }
+
+    #[test]
+    fn is_valid() {
+        assert!(!NamespacedPermissions::default().is_valid());
+        assert!(NamespacedPermissions {
+            namespace: String::from("default"),
+            ..Default::default()
+        }
+        .is_valid());
+        assert!(NamespacedPermissions {
+            namespace_pattern: Some(String::from("*")),
+            ..Default::default()
+        }
+        .is_valid());
     }
 }
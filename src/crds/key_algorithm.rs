@@ -0,0 +1,126 @@
+use std::{fmt::Display, str::FromStr};
+
+use openssl::{
+    ec::{EcGroup, EcKey},
+    hash::MessageDigest,
+    nid::Nid,
+    pkey::{HasPublic, Id, PKey, Private},
+    rsa::Rsa,
+};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::operator::error::{KuoError, KuoResult};
+
+/// Algorithm of the private key generated for a user.
+///
+/// Please make sure that the signer supports the chosen algorithm.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema, clap::ValueEnum,
+)]
+#[serde(rename_all = "kebab-case")]
+pub enum KeyAlgorithm {
+    Rsa2048,
+    #[default]
+    Rsa4096,
+    EcdsaP256,
+    EcdsaP384,
+    Ed25519,
+}
+
+impl KeyAlgorithm {
+    #[must_use]
+    pub const fn as_str(&self) -> &'static str {
+        match self {
+            Self::Rsa2048 => "rsa2048",
+            Self::Rsa4096 => "rsa4096",
+            Self::EcdsaP256 => "ecdsa-p256",
+            Self::EcdsaP384 => "ecdsa-p384",
+            Self::Ed25519 => "ed25519",
+        }
+    }
+
+    /// Generate a new private key.
+    ///
+    /// RSA key generation is slow, so it's better
+    /// to call it in a blocking task.
+    pub fn generate(self) -> KuoResult<PKey<Private>> {
+        tracing::info!("Generating {self} key");
+        let pkey = match self {
+            Self::Rsa2048 => PKey::from_rsa(Rsa::generate(2048)?)?,
+            Self::Rsa4096 => PKey::from_rsa(Rsa::generate(4096)?)?,
+            Self::EcdsaP256 => {
+                let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1)?;
+                PKey::from_ec_key(EcKey::generate(&group)?)?
+            }
+            Self::EcdsaP384 => {
+                let group = EcGroup::from_curve_name(Nid::SECP384R1)?;
+                PKey::from_ec_key(EcKey::generate(&group)?)?
+            }
+            Self::Ed25519 => PKey::generate_ed25519()?,
+        };
+        Ok(pkey)
+    }
+
+    /// Detect the algorithm of an existing key.
+    ///
+    /// Returns `None` for keys which cannot be generated by the operator.
+    #[must_use]
+    pub fn of_key<T: HasPublic>(pkey: &PKey<T>) -> Option<Self> {
+        match pkey.id() {
+            Id::RSA => match pkey.bits() {
+                2048 => Some(Self::Rsa2048),
+                4096 => Some(Self::Rsa4096),
+                _ => None,
+            },
+            Id::EC => match pkey.ec_key().ok()?.group().curve_name()? {
+                Nid::X9_62_PRIME256V1 => Some(Self::EcdsaP256),
+                Nid::SECP384R1 => Some(Self::EcdsaP384),
+                _ => None,
+            },
+            Id::ED25519 => Some(Self::Ed25519),
+            _ => None,
+        }
+    }
+
+    /// Digest to use for signatures made with the given key.
+    ///
+    /// Ed25519 signatures don't use a separate digest.
+    #[must_use]
+    pub fn signature_digest<T: HasPublic>(pkey: &PKey<T>) -> MessageDigest {
+        match pkey.id() {
+            Id::ED25519 => MessageDigest::null(),
+            Id::EC if pkey.bits() > 256 => MessageDigest::sha384(),
+            _ => MessageDigest::sha256(),
+        }
+    }
+}
+
+impl Display for KeyAlgorithm {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for KeyAlgorithm {
+    type Err = KuoError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        <Self as clap::ValueEnum>::from_str(s, true).map_err(|_| KuoError::InvalidUserSecretData)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::ValueEnum;
+
+    use super::KeyAlgorithm;
+
+    #[test]
+    fn algorithm_of_generated_keys() {
+        for algorithm in KeyAlgorithm::value_variants() {
+            let pkey = algorithm.generate().unwrap();
+            assert_eq!(KeyAlgorithm::of_key(&pkey), Some(*algorithm));
+        }
+    }
+}
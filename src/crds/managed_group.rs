@@ -0,0 +1,176 @@
+use std::{collections::BTreeMap, sync::Arc};
+
+use k8s_openapi::api::{core::v1::Namespace, rbac::v1::Subject};
+use kube::{api::ObjectMeta, runtime::reflector::Store, CustomResource, ResourceExt};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::operator::{
+    ctx::OperatorCtx,
+    error::KuoResult,
+    utils::{meta::ObjectMetaKuoExt, rbac},
+};
+
+use super::{
+    inline_permissions::{AppliedPermissions, InlinePermissions},
+    managed_user::ManagedUser,
+};
+
+/// Label set on roles and role bindings created for a group.
+pub const GROUP_LABEL: &str = "kuo.github.io/group";
+
+#[derive(CustomResource, Debug, Serialize, Deserialize, Default, Clone, JsonSchema)]
+#[kube(
+    group = "kuo.github.io",
+    version = "v1",
+    kind = "ManagedGroup",
+    status = "ManagedGroupStatus",
+    printcolumn = r#"
+    {
+        "name": "Members",
+        "type": "string",
+        "description": "Members of the group",
+        "jsonPath": ".status.members"
+    }
+    "#
+)]
+#[serde(rename_all = "camelCase")]
+pub struct ManagedGroupCRD {
+    /// Names of the `ManagedUser` objects which belong to the group.
+    #[serde(default)]
+    pub members: Option<Vec<String>>,
+    /// Users with matching labels belong to the group as well.
+    #[serde(default)]
+    pub user_selector: Option<UserSelector>,
+    /// Permissions granted to all members of the group.
+    #[serde(default)]
+    pub inline_permissions: Option<InlinePermissions>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Default, Clone, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct UserSelector {
+    /// Labels which the user must have. An empty selector matches all users.
+    #[serde(default)]
+    pub match_labels: BTreeMap<String, String>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Default, Debug, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ManagedGroupStatus {
+    /// Generation of the `ManagedGroup` that was last reconciled.
+    #[serde(default)]
+    pub observed_generation: Option<i64>,
+    /// Users bound to the group's roles.
+    #[serde(default)]
+    pub members: Vec<String>,
+    /// Roles created for the group in the `{namespace}/{name}` format.
+    #[serde(default)]
+    pub roles: Vec<String>,
+    /// Cluster roles created for the group.
+    #[serde(default)]
+    pub cluster_roles: Vec<String>,
+    /// Namespaces which don't exist yet.
+    /// Permissions are applied as soon as they are created.
+    #[serde(default)]
+    pub pending_namespaces: Vec<String>,
+    /// Namespaces where permissions could not be applied.
+    #[serde(default)]
+    pub failed_namespaces: Vec<String>,
+}
+
+impl ManagedGroup {
+    /// Check whether the user belongs to the group.
+    #[must_use]
+    pub fn selects(&self, user: &ManagedUser) -> bool {
+        let listed = self
+            .spec
+            .members
+            .as_ref()
+            .is_some_and(|members| members.contains(&user.name_any()));
+        let selected = self.spec.user_selector.as_ref().is_some_and(|selector| {
+            selector
+                .match_labels
+                .iter()
+                .all(|(key, value)| user.labels().get(key) == Some(value))
+        });
+        listed || selected
+    }
+
+    /// Check whether the user is bound to the group's roles.
+    #[must_use]
+    pub fn has_member(&self, user: &ManagedUser) -> bool {
+        self.status
+            .as_ref()
+            .is_some_and(|status| status.members.contains(&user.name_any()))
+    }
+
+    /// Check whether a change of the namespace affects the group's roles.
+    #[must_use]
+    pub fn affected_by_namespace(&self, namespace: &Namespace) -> bool {
+        let permissions = self
+            .spec
+            .inline_permissions
+            .as_ref()
+            .and_then(|p| p.namespaced_permissions.as_deref())
+            .unwrap_or_default();
+        let roles = self
+            .status
+            .as_ref()
+            .map(|status| status.roles.as_slice())
+            .unwrap_or_default();
+        rbac::namespace_affects(namespace, permissions, roles)
+    }
+
+    /// Get all users which belong to the group.
+    ///
+    /// Users that are disabled or outside of their access window are skipped.
+    #[must_use]
+    pub fn members(&self, users: &Store<ManagedUser>) -> Vec<Arc<ManagedUser>> {
+        let mut members = users
+            .state()
+            .into_iter()
+            .filter(|user| self.selects(user) && user.has_access())
+            .collect::<Vec<_>>();
+        members.sort_by_key(|user| user.name_any());
+        members
+    }
+
+    /// Create roles for the group and bind all members to them.
+    pub async fn apply(
+        &self,
+        members: &[Arc<ManagedUser>],
+        ctx: Arc<OperatorCtx>,
+    ) -> KuoResult<AppliedPermissions> {
+        let mut metadata = ObjectMeta {
+            name: Some(format!("kuo-group-{}", self.name_any())),
+            ..Default::default()
+        };
+        metadata.add_owner(self);
+        metadata.insert_label(GROUP_LABEL, self.name_any());
+        let subjects = members
+            .iter()
+            .map(|user| Subject {
+                kind: String::from("User"),
+                name: user.identity(ctx.args.identity_generations),
+                namespace: None,
+                api_group: None,
+            })
+            .collect::<Vec<_>>();
+        let permissions = self.spec.inline_permissions.clone().unwrap_or_default();
+        rbac::apply_permissions(
+            &ctx,
+            &metadata,
+            (GROUP_LABEL, &self.name_any()),
+            &permissions.namespaced_permissions.unwrap_or_default(),
+            &permissions.cluster_permissions.unwrap_or_default(),
+            &subjects,
+        )
+        .await
+    }
+
+    /// Status of the group, created if the group has none yet.
+    pub fn status_or_default(&mut self) -> &mut ManagedGroupStatus {
+        self.status.get_or_insert_with(ManagedGroupStatus::default)
+    }
+}
@@ -1,8 +1,17 @@
 use std::{str::FromStr, sync::Arc};
 
 use base64::{engine::general_purpose::STANDARD as BASE64_STANDARD, Engine};
-use k8s_openapi::api::core::v1::Secret;
-use kube::{api::ObjectMeta, config::NamedContext, CustomResource, ResourceExt};
+use chrono::{DateTime, Utc};
+use k8s_openapi::api::{
+    core::v1::{Namespace, Secret},
+    rbac::v1::Subject,
+};
+use kube::{
+    api::{ObjectMeta, Patch, PatchParams},
+    config::NamedContext,
+    runtime::events::{Event, Recorder},
+    CustomResource, Resource, ResourceExt,
+};
 use lettre::{
     message::{header::ContentType, Attachment, Mailbox, SinglePart},
     Address, AsyncTransport,
@@ -13,16 +22,23 @@ use serde::{Deserialize, Serialize};
 use crate::operator::{
     ctx::OperatorCtx,
     error::{KuoError, KuoResult},
-    utils::{meta::ObjectMetaKuoExt, resource::KuoResourceExt},
+    utils::{meta::ObjectMetaKuoExt, rbac, resource::KuoResourceExt},
 };
 
-use super::inline_permissions::InlinePermissions;
+use super::{
+    access_request::AccessRequest,
+    inline_permissions::{AppliedPermissions, InlinePermissions},
+    key_algorithm::KeyAlgorithm,
+    role_refs::RoleRefs,
+    status::{is_condition_true, set_condition, Condition},
+};
 
 #[derive(CustomResource, Debug, Serialize, Deserialize, Default, Clone, JsonSchema)]
 #[kube(
     group = "kuo.github.io",
     version = "v1",
     kind = "ManagedUser",
+    status = "ManagedUserStatus",
     printcolumn = r#"
     {
         "name":"Email", 
@@ -35,6 +51,38 @@ use super::inline_permissions::InlinePermissions;
         "type": "string", 
         "description": "User's real name", 
         "jsonPath": ".spec.full_name"
+    },
+    {
+        "name": "Ready",
+        "type": "string",
+        "description": "Whether the user can access the cluster",
+        "jsonPath": ".status.conditions[?(@.type==\"Ready\")].status"
+    },
+    {
+        "name": "Disabled",
+        "type": "string",
+        "description": "Whether the user is suspended",
+        "jsonPath": ".status.conditions[?(@.type==\"Disabled\")].status"
+    },
+    {
+        "name": "Valid Until",
+        "type": "string",
+        "description": "Time until which the user has access",
+        "jsonPath": ".spec.validUntil",
+        "priority": 1
+    },
+    {
+        "name": "Identity",
+        "type": "string",
+        "description": "Name of the user in the current certificate",
+        "jsonPath": ".status.identity",
+        "priority": 1
+    },
+    {
+        "name": "Expires",
+        "type": "string",
+        "description": "Expiration time of the user's certificate",
+        "jsonPath": ".status.certExpiresAt"
     }
     "#
 )]
@@ -51,6 +99,163 @@ pub struct ManagedUserCRD {
     /// List of inlined permissions.
     #[serde(default)]
     pub inline_permissions: Option<InlinePermissions>,
+    /// Existing roles and cluster roles to bind to the user.
+    #[serde(default)]
+    pub role_refs: Option<RoleRefs>,
+    /// Requested lifetime of the user's certificate in seconds.
+    /// Overrides the operator-wide default.
+    #[validate(range(min = 600))]
+    #[serde(default)]
+    pub cert_expiration_seconds: Option<i32>,
+    /// Algorithm of the user's private key.
+    /// Overrides the operator-wide default.
+    /// Changing it replaces the user's key and certificate.
+    #[serde(default)]
+    pub key_algorithm: Option<KeyAlgorithm>,
+    /// Kubernetes groups the user belongs to.
+    /// Groups are encoded in the user's certificate,
+    /// so changing them results in a new certificate.
+    /// Reserved `system:` groups are not allowed.
+    #[schemars(schema_with = "groups_rule")]
+    #[serde(default)]
+    pub groups: Option<Vec<String>>,
+    /// PEM-encoded certificate signing request.
+    ///
+    /// If set, the operator doesn't generate a private key for the user
+    /// and issues a certificate for this request instead.
+    /// The subject must contain the user's name as `CN`
+    /// and the user's groups as `O` entries.
+    #[serde(default)]
+    pub certificate_request: Option<String>,
+    /// Suspend the user without deleting it.
+    ///
+    /// All roles and role bindings of the user are removed
+    /// and restored once the user is enabled again.
+    #[serde(default)]
+    pub disabled: Option<bool>,
+    /// Time since which the user has access to the cluster.
+    #[serde(default)]
+    pub valid_from: Option<DateTime<Utc>>,
+    /// Time until which the user has access to the cluster.
+    ///
+    /// Permissions are removed afterwards,
+    /// and certificates are never issued beyond this time.
+    #[serde(default)]
+    pub valid_until: Option<DateTime<Utc>>,
+}
+
+/// Position of the current time relative to the user's access window.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessWindow {
+    /// `validFrom` is in the future.
+    NotYetValid,
+    /// The user has access.
+    Active,
+    /// `validUntil` has passed.
+    Expired,
+}
+
+/// Types of conditions reported in the `ManagedUser` status.
+pub mod conditions {
+    /// Private key for the user has been generated.
+    pub const KEY_GENERATED: &str = "KeyGenerated";
+    /// Certificate signing request is waiting to be signed.
+    pub const CSR_PENDING: &str = "CSRPending";
+    /// User has a signed certificate.
+    pub const CERTIFICATE_ISSUED: &str = "CertificateIssued";
+    /// Inline permissions and role references are applied to the cluster.
+    pub const PERMISSIONS_SYNCED: &str = "PermissionsSynced";
+    /// Kubeconfig has been sent to the user.
+    pub const KUBECONFIG_DELIVERED: &str = "KubeconfigDelivered";
+    /// Certificate request has been denied or has failed.
+    pub const ISSUANCE_FAILED: &str = "IssuanceFailed";
+    /// User is suspended and has no permissions.
+    pub const DISABLED: &str = "Disabled";
+    /// Current time is within the user's access window.
+    pub const ACCESS_VALID: &str = "AccessValid";
+    /// User can access the cluster.
+    pub const READY: &str = "Ready";
+}
+
+/// Annotation used to request a new certificate after the previous request
+/// has been denied or has failed. Every new value triggers a single re-request.
+pub const REISSUE_ANNOTATION: &str = "kuo.github.io/reissue";
+
+/// Annotation used to rotate the user's credentials, e.g. after a laptop is lost.
+/// Every new value triggers generation of a new key and certificate.
+pub const ROTATE_ANNOTATION: &str = "kuo.github.io/rotate";
+
+#[derive(Deserialize, Serialize, Clone, Default, Debug, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ManagedUserStatus {
+    /// Latest observations of the user's state.
+    #[serde(default)]
+    pub conditions: Vec<Condition>,
+    /// Generation of the `ManagedUser` that was last reconciled.
+    #[serde(default)]
+    pub observed_generation: Option<i64>,
+    /// Expiration time of the user's certificate in RFC 3339 format.
+    #[serde(default)]
+    pub cert_expires_at: Option<String>,
+    /// Roles created for the user in the `{namespace}/{name}` format.
+    #[serde(default)]
+    pub roles: Vec<String>,
+    /// Cluster roles created for the user.
+    #[serde(default)]
+    pub cluster_roles: Vec<String>,
+    /// Role bindings to referenced roles in the `{namespace}/{name}` format.
+    #[serde(default)]
+    pub role_bindings: Vec<String>,
+    /// Cluster role bindings to referenced cluster roles.
+    #[serde(default)]
+    pub cluster_role_bindings: Vec<String>,
+    /// Last handled value of the `kuo.github.io/reissue` annotation.
+    #[serde(default)]
+    pub last_reissue: Option<String>,
+    /// Last handled value of the `kuo.github.io/rotate` annotation.
+    #[serde(default)]
+    pub last_rotation: Option<String>,
+    /// Generation of the user's credentials.
+    ///
+    /// It's the Unix time when the current key was generated, so it never repeats,
+    /// even if the user is recreated or its status is lost.
+    #[serde(default)]
+    pub credential_generation: Option<i64>,
+    /// Name of the user in the current certificate and role bindings.
+    #[serde(default)]
+    pub identity: Option<String>,
+}
+
+impl ManagedUserStatus {
+    pub fn set_condition(
+        &mut self,
+        type_: &str,
+        status: bool,
+        reason: &str,
+        message: Option<String>,
+    ) {
+        set_condition(&mut self.conditions, type_, status, reason, message);
+    }
+
+    /// Update the `Ready` condition based on other conditions.
+    pub fn refresh_ready(&mut self) {
+        let cert_issued = is_condition_true(&self.conditions, conditions::CERTIFICATE_ISSUED);
+        let permissions_synced =
+            is_condition_true(&self.conditions, conditions::PERMISSIONS_SYNCED);
+        let disabled = is_condition_true(&self.conditions, conditions::DISABLED);
+        let outside_window = self
+            .conditions
+            .iter()
+            .any(|c| c.type_ == conditions::ACCESS_VALID && c.status == "False");
+        let (ready, reason) = match (cert_issued, permissions_synced) {
+            _ if disabled => (false, "Disabled"),
+            _ if outside_window => (false, "AccessNotValid"),
+            (true, true) => (true, "Ready"),
+            (false, _) => (false, "CertificateNotIssued"),
+            (true, false) => (false, "PermissionsNotSynced"),
+        };
+        self.set_condition(conditions::READY, ready, reason, None);
+    }
 }
 
 /// Struct that holds user's secret data
@@ -59,20 +264,32 @@ pub struct ManagedUserCRD {
 pub struct ManagedUserSecretData {
     /// This is a private key, used by the client.
     /// This key is used for issuing certificate sign requests.
-    pub pkey: String,
+    /// It's empty if the user provides their own certificate request.
+    pub pkey: Option<String>,
     /// Resulting certificate for a user.
     pub cert: Option<String>,
     /// Generated Kubeconfig
     pub kubeconfig: Option<String>,
+    /// Expiration time of the certificate in RFC 3339 format.
+    pub expiration: Option<String>,
+    /// Algorithm of the private key.
+    pub algorithm: Option<KeyAlgorithm>,
+}
+
+impl ManagedUserSecretData {
+    /// Keys of the user's secret.
+    pub const KEYS: [&'static str; 5] = ["pkey", "cert", "kubeconfig", "expiration", "algorithm"];
 }
 
 impl From<&ManagedUserSecretData> for std::collections::BTreeMap<String, k8s_openapi::ByteString> {
     fn from(value: &ManagedUserSecretData) -> Self {
         let mut map = Self::new();
-        map.insert(
-            "pkey".to_string(),
-            k8s_openapi::ByteString(value.pkey.bytes().collect()),
-        );
+        if let Some(pkey) = &value.pkey {
+            map.insert(
+                "pkey".to_string(),
+                k8s_openapi::ByteString(pkey.bytes().collect()),
+            );
+        }
         if let Some(cert) = &value.cert {
             map.insert(
                 "cert".to_string(),
@@ -85,6 +302,18 @@ impl From<&ManagedUserSecretData> for std::collections::BTreeMap<String, k8s_ope
                 k8s_openapi::ByteString(kubeconfig.bytes().collect()),
             );
         }
+        if let Some(expiration) = &value.expiration {
+            map.insert(
+                "expiration".to_string(),
+                k8s_openapi::ByteString(expiration.bytes().collect()),
+            );
+        }
+        if let Some(algorithm) = &value.algorithm {
+            map.insert(
+                "algorithm".to_string(),
+                k8s_openapi::ByteString(algorithm.as_str().bytes().collect()),
+            );
+        }
         map
     }
 }
@@ -97,20 +326,29 @@ impl TryFrom<std::collections::BTreeMap<String, k8s_openapi::ByteString>>
     fn try_from(
         value: std::collections::BTreeMap<String, k8s_openapi::ByteString>,
     ) -> Result<Self, Self::Error> {
-        let Some(pkey) = value.get("pkey") else {
-            return Err(KuoError::InvalidUserSecretData);
-        };
-        let pkey = String::from_utf8(pkey.0.clone())?;
+        let pkey = value
+            .get("pkey")
+            .map(|v| String::from_utf8(v.0.clone()))
+            .transpose()?;
         let cert = value
             .get("cert")
             .and_then(|v| String::from_utf8(v.0.clone()).ok());
         let kubeconfig = value
             .get("kubeconfig")
             .and_then(|v| String::from_utf8(v.0.clone()).ok());
+        let expiration = value
+            .get("expiration")
+            .and_then(|v| String::from_utf8(v.0.clone()).ok());
+        let algorithm = value
+            .get("algorithm")
+            .and_then(|v| String::from_utf8(v.0.clone()).ok())
+            .and_then(|v| KeyAlgorithm::from_str(&v).ok());
         Ok(Self {
             pkey,
             cert,
             kubeconfig,
+            expiration,
+            algorithm,
         })
     }
 }
@@ -138,22 +376,183 @@ pub fn immutable_rule<T: JsonSchema>(
     schema.into()
 }
 
+/// Forbid reserved `system:` groups.
+pub fn groups_rule(gen: &mut schemars::gen::SchemaGenerator) -> schemars::schema::Schema {
+    let mut schema: SchemaObject = <Option<Vec<String>>>::json_schema(gen).into();
+    schema.extensions.insert(
+        String::from("x-kubernetes-validations"),
+        serde_json::json!([
+            {
+                "rule": "self.all(group, !group.startsWith('system:'))",
+                "message": "Groups with the reserved `system:` prefix are not allowed."
+            }
+        ]),
+    );
+    schema.into()
+}
+
+/// Groups which must not be encoded in certificates issued by the operator.
+///
+/// Reserved `system:` groups, such as `system:masters`, are always forbidden.
+/// If `allowed` isn't empty, groups missing from it are forbidden as well.
+#[must_use]
+pub fn forbidden_groups(groups: &[String], allowed: &[String]) -> Vec<String> {
+    groups
+        .iter()
+        .filter(|group| {
+            group.starts_with("system:") || !(allowed.is_empty() || allowed.contains(group))
+        })
+        .cloned()
+        .collect()
+}
+
 impl ManagedUser {
+    /// Sorted list of the user's groups without duplicates.
+    #[must_use]
+    pub fn groups(&self) -> Vec<String> {
+        let mut groups = self.spec.groups.clone().unwrap_or_default();
+        groups.sort();
+        groups.dedup();
+        groups
+    }
+
+    #[must_use]
+    pub fn is_disabled(&self) -> bool {
+        self.spec.disabled.unwrap_or_default()
+    }
+
+    /// Name of the user in certificates and role bindings.
+    ///
+    /// With identity generations enabled, it's suffixed with the credential generation,
+    /// so certificates of older generations aren't bound to any role.
+    /// Users without a generation get a new key, since their plain name could be
+    /// bound to certificates issued before the option was enabled.
+    #[must_use]
+    pub fn identity(&self, identity_generations: bool) -> String {
+        let generation = self
+            .status
+            .as_ref()
+            .and_then(|status| status.credential_generation);
+        match generation {
+            Some(generation) if identity_generations => {
+                format!("{}#{generation}", self.name_any())
+            }
+            _ => self.name_any(),
+        }
+    }
+
+    /// Subjects to bind the user's permissions to.
+    ///
+    /// If `bindGroups` is set in the inline permissions,
+    /// the user's groups are bound as well.
+    #[must_use]
+    pub fn rbac_subjects(&self, identity_generations: bool) -> Vec<Subject> {
+        let mut subjects = vec![Subject {
+            kind: String::from("User"),
+            name: self.identity(identity_generations),
+            namespace: None,
+            api_group: None,
+        }];
+        let bind_groups = self
+            .spec
+            .inline_permissions
+            .as_ref()
+            .and_then(|permissions| permissions.bind_groups)
+            .unwrap_or_default();
+        if bind_groups {
+            subjects.extend(self.groups().into_iter().map(|group| Subject {
+                kind: String::from("Group"),
+                name: group,
+                namespace: None,
+                api_group: Some(String::from("rbac.authorization.k8s.io")),
+            }));
+        }
+        subjects
+    }
+
+    /// Algorithm of the user's private key.
+    #[inline]
+    #[must_use]
+    pub fn key_algorithm(&self, default: KeyAlgorithm) -> KeyAlgorithm {
+        self.spec.key_algorithm.unwrap_or(default)
+    }
+
+    /// Lifetime of the user's certificate in seconds.
+    #[inline]
+    #[must_use]
+    pub fn cert_expiration_seconds(&self, default: i32) -> i32 {
+        let lifetime = self.spec.cert_expiration_seconds.unwrap_or(default);
+        let Some(valid_until) = self.spec.valid_until else {
+            return lifetime;
+        };
+        // Kubernetes doesn't issue certificates for less than 10 minutes.
+        let remaining = (valid_until - Utc::now()).num_seconds().max(600);
+        i32::try_from(remaining).map_or(lifetime, |remaining| remaining.min(lifetime))
+    }
+
+    /// Position of the given time relative to the user's access window.
+    #[must_use]
+    pub fn access_window(&self, now: DateTime<Utc>) -> AccessWindow {
+        if self.spec.valid_from.is_some_and(|from| now < from) {
+            AccessWindow::NotYetValid
+        } else if self.spec.valid_until.is_some_and(|until| now >= until) {
+            AccessWindow::Expired
+        } else {
+            AccessWindow::Active
+        }
+    }
+
+    /// Next time at which the user's access window starts or ends.
+    #[must_use]
+    pub fn next_access_boundary(&self, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        match self.access_window(now) {
+            AccessWindow::NotYetValid => self.spec.valid_from,
+            AccessWindow::Active => self.spec.valid_until,
+            AccessWindow::Expired => None,
+        }
+    }
+
+    /// Whether the user should have permissions in the cluster right now.
+    #[must_use]
+    pub fn has_access(&self) -> bool {
+        !self.is_disabled() && self.access_window(Utc::now()) == AccessWindow::Active
+    }
+
+    /// Check whether a change of the namespace affects the user's roles.
+    #[must_use]
+    pub fn affected_by_namespace(&self, namespace: &Namespace) -> bool {
+        let permissions = self
+            .spec
+            .inline_permissions
+            .as_ref()
+            .and_then(|p| p.namespaced_permissions.as_deref())
+            .unwrap_or_default();
+        let roles = self
+            .status
+            .as_ref()
+            .map(|status| status.roles.as_slice())
+            .unwrap_or_default();
+        rbac::namespace_affects(namespace, permissions, roles)
+    }
+
+    /// Build kubeconfig for the user.
+    ///
+    /// If the private key is not known, the kubeconfig
+    /// is generated without `client-key-data`.
     #[inline]
     #[must_use]
     pub fn build_kubeconfig(
         &self,
         kube_addr: &str,
         cluster_name: &Option<String>,
-        private_key: &str,
+        private_key: Option<&str>,
         client_cert: &str,
         root_cert: &str,
     ) -> kube::config::Kubeconfig {
         let mut kubeconfig = kube::config::Kubeconfig::default();
         let cluster_name_string = cluster_name
-            .as_ref()
-            .unwrap_or(&String::from("cluster"))
-            .to_string();
+            .clone()
+            .unwrap_or_else(|| String::from("cluster"));
         // This will create either {cluster}-{username} string or just {username}.
         let username = format!(
             "{}{}",
@@ -174,15 +573,14 @@ impl ManagedUser {
             name: username.clone(),
             auth_info: Some(kube::config::AuthInfo {
                 client_certificate_data: Some(BASE64_STANDARD.encode(client_cert)),
-                client_key_data: Some(BASE64_STANDARD.encode(private_key).into()),
+                client_key_data: private_key.map(|key| BASE64_STANDARD.encode(key).into()),
                 ..Default::default()
             }),
         });
         kubeconfig.contexts.push(NamedContext {
             name: cluster_name
-                .as_ref()
-                .unwrap_or(&String::from("default"))
-                .to_string(),
+                .clone()
+                .unwrap_or_else(|| String::from("default")),
             context: Some(kube::config::Context {
                 cluster: cluster_name_string,
                 user: username,
@@ -193,16 +591,23 @@ impl ManagedUser {
         kubeconfig
     }
 
-    pub async fn send_kubeconfig(&self, ctx: Arc<OperatorCtx>, kubeconfig: &str) -> KuoResult<()> {
+    /// Send kubeconfig to the user's email.
+    ///
+    /// Returns `false` if the user has no email or SMTP is not configured.
+    pub async fn send_kubeconfig(
+        &self,
+        ctx: Arc<OperatorCtx>,
+        kubeconfig: &str,
+    ) -> KuoResult<bool> {
         let Some(email) = &self.spec.email else {
-            return Ok(());
+            return Ok(false);
         };
         let Some(smtp) = &ctx.smtp else {
-            return Ok(());
+            return Ok(false);
         };
         let Some(smtp_args) = &ctx.args.smtp_args else {
             tracing::warn!("Cannot send kubeconfig. SMTP not configured.",);
-            return Ok(());
+            return Ok(false);
         };
         let kube_config_attachement = Attachment::new(String::from("kubeconfig.yaml"))
             .body(String::from(kubeconfig), ContentType::TEXT_PLAIN);
@@ -228,7 +633,7 @@ impl ManagedUser {
             )
             )).singlepart(kube_config_attachement))?;
         smtp.send(msg).await?;
-        Ok(())
+        Ok(true)
     }
 
     pub async fn get_secret(
@@ -246,32 +651,93 @@ impl ManagedUser {
         }
     }
 
+    /// Store the user's data in the secret.
+    ///
+    /// Keys missing in the data are removed from the secret.
     pub async fn set_secret(
         &self,
         api: kube::Api<Secret>,
         data: &ManagedUserSecretData,
+        force: bool,
     ) -> KuoResult<()> {
         let name = format!("{}-data", self.name_any());
         let mut metadata = ObjectMeta {
-            name: Some(name),
+            name: Some(name.clone()),
             ..Default::default()
         };
         metadata.add_owner(self);
+        let data = std::collections::BTreeMap::from(data);
         let secret = Secret {
-            data: Some(data.into()),
+            data: Some(data.clone()),
             metadata,
             ..Default::default()
-        };
-        secret.patch_or_create(api).await?;
+        }
+        .patch_or_create(api.clone(), force)
+        .await?;
+        // Keys written before the operator switched to server-side apply
+        // are not owned by its field manager, so they have to be removed explicitly.
+        let stale_keys = secret
+            .data
+            .unwrap_or_default()
+            .into_keys()
+            .filter(|key| {
+                ManagedUserSecretData::KEYS.contains(&key.as_str()) && !data.contains_key(key)
+            })
+            .map(|key| (key, None::<String>))
+            .collect::<std::collections::BTreeMap<_, _>>();
+        if stale_keys.is_empty() {
+            return Ok(());
+        }
+        api.patch(
+            &name,
+            &PatchParams::default(),
+            &Patch::Merge(serde_json::json!({ "data": stale_keys })),
+        )
+        .await?;
         Ok(())
     }
 
     #[inline]
-    pub async fn sync_permissions(&self, ctx: Arc<OperatorCtx>) -> KuoResult<()> {
-        tracing::info!("Syncing permissions");
-        if let Some(permissions) = &self.spec.inline_permissions {
-            permissions.apply(self, ctx.clone()).await?;
+    pub async fn sync_permissions(&self, ctx: Arc<OperatorCtx>) -> KuoResult<AppliedPermissions> {
+        if !self.has_access() {
+            tracing::info!(
+                "User is disabled or outside of the access window. Removing permissions"
+            );
+            InlinePermissions::remove_all(self, ctx.clone()).await?;
+            AccessRequest::revoke_all_for_user(self, ctx).await?;
+            return Ok(AppliedPermissions::default());
         }
+        tracing::info!("Syncing permissions");
+        let mut applied = match &self.spec.inline_permissions {
+            Some(permissions) => permissions.apply(self, ctx.clone()).await?,
+            None => AppliedPermissions::default(),
+        };
+        // Bindings of roles which are no longer referenced are removed as well.
+        self.spec
+            .role_refs
+            .clone()
+            .unwrap_or_default()
+            .apply(self, ctx, &mut applied)
+            .await?;
+        Ok(applied)
+    }
+
+    /// Publish an event about the user.
+    pub async fn publish_event(&self, ctx: Arc<OperatorCtx>, event: Event) -> KuoResult<()> {
+        Recorder::new(
+            ctx.client.clone(),
+            ctx.reporter.clone(),
+            self.object_ref(&()),
+        )
+        .publish(event)
+        .await?;
         Ok(())
     }
+
+    /// Status of the user, created if the user has none yet.
+    ///
+    /// Reconcilers modify the status in place, it's saved once the reconciliation is done.
+    pub fn status_or_default(&mut self) -> &mut ManagedUserStatus {
+        self.status.get_or_insert_with(ManagedUserStatus::default)
+    }
 }
@@ -1,2 +1,7 @@
+pub mod access_request;
 pub mod inline_permissions;
+pub mod key_algorithm;
+pub mod managed_group;
 pub mod managed_user;
+pub mod role_refs;
+pub mod status;
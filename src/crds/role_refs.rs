@@ -0,0 +1,214 @@
+use std::{collections::HashSet, sync::Arc};
+
+use k8s_openapi::{
+    api::rbac::v1::{ClusterRole, ClusterRoleBinding, Role, RoleBinding, RoleRef},
+    Resource,
+};
+use kube::{api::ObjectMeta, runtime::reflector::Store, ResourceExt};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::operator::{
+    cache::{labelled, MANAGED_BY, MANAGED_BY_LABEL},
+    ctx::OperatorCtx,
+    error::KuoResult,
+    utils::{digest::digest, meta::ObjectMetaKuoExt, rbac, resource::KuoResourceExt},
+};
+
+use super::{inline_permissions::AppliedPermissions, managed_user::ManagedUser};
+
+/// Label which distinguishes bindings created for `roleRefs`
+/// from the bindings of inline permissions.
+pub const ROLE_REF_LABEL: &str = "kuo.github.com/role-ref";
+
+#[derive(Debug, Serialize, Deserialize, Default, Clone, JsonSchema)]
+pub struct NamespacedRoleRef {
+    /// Namespace to create the role binding in.
+    pub namespace: String,
+    /// Name of the referenced role.
+    pub name: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Default, Clone, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct RoleRefs {
+    /// Names of cluster roles to bind cluster-wide.
+    pub cluster_roles: Option<Vec<String>>,
+    /// Cluster roles to bind in a single namespace.
+    pub namespaced_cluster_roles: Option<Vec<NamespacedRoleRef>>,
+    /// Existing roles to bind in their namespace.
+    pub roles: Option<Vec<NamespacedRoleRef>>,
+}
+
+/// Name of the binding for the referenced role.
+fn binding_name(user: &ManagedUser, kind: &str, role: &impl Serialize) -> KuoResult<String> {
+    Ok(format!("{}-{}", user.name_any(), digest(&(kind, role))?))
+}
+
+fn binding_metadata(user: &ManagedUser, name: &str) -> ObjectMeta {
+    let mut metadata = ObjectMeta::default();
+    metadata.add_owner(user);
+    metadata.name = Some(String::from(name));
+    metadata.insert_label("kuo.github.com/user", user.name_any());
+    metadata.insert_label(ROLE_REF_LABEL, "true");
+    metadata.insert_label(MANAGED_BY_LABEL, MANAGED_BY);
+    metadata
+}
+
+/// Get cached bindings created for the user's `roleRefs`.
+fn cached_bindings<K>(user: &ManagedUser, cache: &Store<K>) -> Vec<Arc<K>>
+where
+    K: kube::Resource<DynamicType = ()> + Clone + 'static,
+{
+    labelled(cache, ("kuo.github.com/user", &user.name_any()))
+        .into_iter()
+        .filter(|binding| binding.labels().contains_key(ROLE_REF_LABEL))
+        .collect()
+}
+
+impl NamespacedRoleRef {
+    async fn apply<K: Resource>(
+        &self,
+        user: &ManagedUser,
+        ctx: Arc<OperatorCtx>,
+        reverted: &mut Vec<String>,
+    ) -> KuoResult<String> {
+        let name = binding_name(user, K::KIND, self)?;
+        let mut metadata = binding_metadata(user, &name);
+        metadata.namespace = Some(self.namespace.clone());
+        let role_binding = RoleBinding {
+            metadata,
+            role_ref: RoleRef {
+                api_group: String::from(Role::GROUP),
+                kind: String::from(K::KIND),
+                name: self.name.clone(),
+            },
+            subjects: Some(user.rbac_subjects(ctx.args.identity_generations)),
+        };
+        role_binding
+            .patch_or_create_cached(
+                kube::Api::namespaced(ctx.client.clone(), &self.namespace),
+                &ctx.cache.role_bindings,
+                ctx.args.force_conflicts,
+            )
+            .await?
+            .record(reverted);
+        Ok(name)
+    }
+}
+
+impl RoleRefs {
+    /// Bind referenced roles in their namespaces.
+    ///
+    /// Role bindings created for the user's `roleRefs`
+    /// which aren't referenced anymore are removed.
+    #[allow(clippy::missing_panics_doc)]
+    async fn apply_namespaced(
+        &self,
+        user: &ManagedUser,
+        ctx: Arc<OperatorCtx>,
+        applied: &mut AppliedPermissions,
+    ) -> KuoResult<()> {
+        let mut known_bindings = HashSet::new();
+        let namespaced_cluster_roles = self.namespaced_cluster_roles.iter().flatten();
+        let roles = self.roles.iter().flatten();
+        let references = namespaced_cluster_roles
+            .map(|role_ref| (role_ref, true))
+            .chain(roles.map(|role_ref| (role_ref, false)));
+        for (role_ref, cluster_role) in references {
+            let res = if cluster_role {
+                role_ref
+                    .apply::<ClusterRole>(user, ctx.clone(), &mut applied.reverted)
+                    .await
+            } else {
+                role_ref
+                    .apply::<Role>(user, ctx.clone(), &mut applied.reverted)
+                    .await
+            };
+            match res {
+                Ok(name) => {
+                    let binding = format!("{}/{name}", role_ref.namespace);
+                    applied.role_bindings.push(binding.clone());
+                    known_bindings.insert(binding);
+                }
+                Err(err) => {
+                    tracing::warn!("Failed to bind referenced role. {err}");
+                    applied.failed_namespaces.push(role_ref.namespace.clone());
+                }
+            }
+        }
+        for binding in cached_bindings(user, &ctx.cache.role_bindings) {
+            // SAFETY: Role bindings are always namespaced.
+            let namespace = binding.namespace().unwrap();
+            if known_bindings.contains(&format!("{namespace}/{}", binding.name_any())) {
+                continue;
+            }
+            rbac::delete_if_exists(
+                kube::Api::<RoleBinding>::namespaced(ctx.client.clone(), &namespace),
+                &binding.name_any(),
+            )
+            .await?;
+        }
+        Ok(())
+    }
+
+    /// Bind referenced cluster roles cluster-wide.
+    ///
+    /// Cluster role bindings created for the user's `roleRefs`
+    /// which aren't referenced anymore are removed.
+    async fn apply_cluster(
+        &self,
+        user: &ManagedUser,
+        ctx: Arc<OperatorCtx>,
+        applied: &mut AppliedPermissions,
+    ) -> KuoResult<()> {
+        let mut known_bindings = HashSet::new();
+        for cluster_role in self.cluster_roles.iter().flatten() {
+            let name = binding_name(user, ClusterRoleBinding::KIND, cluster_role)?;
+            let role_binding = ClusterRoleBinding {
+                metadata: binding_metadata(user, &name),
+                role_ref: RoleRef {
+                    api_group: String::from(ClusterRole::GROUP),
+                    kind: String::from(ClusterRole::KIND),
+                    name: cluster_role.clone(),
+                },
+                subjects: Some(user.rbac_subjects(ctx.args.identity_generations)),
+            };
+            role_binding
+                .patch_or_create_cached(
+                    kube::Api::all(ctx.client.clone()),
+                    &ctx.cache.cluster_role_bindings,
+                    ctx.args.force_conflicts,
+                )
+                .await?
+                .record(&mut applied.reverted);
+            applied.cluster_role_bindings.push(name.clone());
+            known_bindings.insert(name);
+        }
+        for binding in cached_bindings(user, &ctx.cache.cluster_role_bindings) {
+            if known_bindings.contains(&binding.name_any()) {
+                continue;
+            }
+            rbac::delete_if_exists(
+                kube::Api::<ClusterRoleBinding>::all(ctx.client.clone()),
+                &binding.name_any(),
+            )
+            .await?;
+        }
+        Ok(())
+    }
+
+    /// Bind the referenced roles to the user.
+    ///
+    /// Bindings are owned by the user and removed
+    /// once the roles are no longer referenced.
+    pub async fn apply(
+        &self,
+        user: &ManagedUser,
+        ctx: Arc<OperatorCtx>,
+        applied: &mut AppliedPermissions,
+    ) -> KuoResult<()> {
+        self.apply_namespaced(user, ctx.clone(), applied).await?;
+        self.apply_cluster(user, ctx, applied).await
+    }
+}
@@ -0,0 +1,59 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// Condition of a resource managed by the operator.
+///
+/// Follows the semantics of the standard kubernetes conditions.
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Condition {
+    /// Type of the condition.
+    #[serde(rename = "type")]
+    pub type_: String,
+    /// Status of the condition. One of `True`, `False` or `Unknown`.
+    pub status: String,
+    /// Machine-readable reason for the condition's last transition.
+    pub reason: String,
+    /// Human-readable message with details about the transition.
+    #[serde(default)]
+    pub message: Option<String>,
+    /// Last time the condition transitioned from one status to another.
+    #[serde(default)]
+    pub last_transition_time: Option<String>,
+}
+
+/// Set condition in the list.
+///
+/// Transition time is updated only if the status of the condition has changed.
+pub fn set_condition(
+    conditions: &mut Vec<Condition>,
+    type_: &str,
+    status: bool,
+    reason: &str,
+    message: Option<String>,
+) {
+    let status = String::from(if status { "True" } else { "False" });
+    if let Some(condition) = conditions.iter_mut().find(|c| c.type_ == type_) {
+        if condition.status != status {
+            condition.last_transition_time = Some(chrono::Utc::now().to_rfc3339());
+        }
+        condition.status = status;
+        condition.reason = String::from(reason);
+        condition.message = message;
+        return;
+    }
+    conditions.push(Condition {
+        type_: String::from(type_),
+        status,
+        reason: String::from(reason),
+        message,
+        last_transition_time: Some(chrono::Utc::now().to_rfc3339()),
+    });
+}
+
+#[must_use]
+pub fn is_condition_true(conditions: &[Condition], type_: &str) -> bool {
+    conditions
+        .iter()
+        .any(|c| c.type_ == type_ && c.status == "True")
+}
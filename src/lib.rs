@@ -12,7 +12,9 @@
         clippy::module_name_repetitions, 
         // Yo, the hell you should put
         // it in docs, if signature is clear as sky.
-        clippy::missing_errors_doc
+        clippy::missing_errors_doc,
+        // `60 * 10` seconds reads better than minutes here.
+        clippy::duration_suboptimal_units
     )
 ]
 pub mod crds;
@@ -0,0 +1,192 @@
+use std::sync::Arc;
+
+use futures::{Stream, StreamExt};
+use k8s_openapi::api::{
+    certificates::v1::CertificateSigningRequest,
+    core::v1::Namespace,
+    rbac::v1::{ClusterRole, ClusterRoleBinding, Role, RoleBinding},
+};
+use kube::{
+    runtime::{
+        reflector::{self, Store},
+        watcher, WatchStreamExt,
+    },
+    ResourceExt,
+};
+use serde::de::DeserializeOwned;
+use tokio::sync::broadcast;
+
+use crate::crds::managed_user::ManagedUser;
+
+use super::error::{KuoError, KuoResult};
+
+/// Label set on all objects created by the operator.
+pub const MANAGED_BY_LABEL: &str = "app.kubernetes.io/managed-by";
+
+/// Value of the [`MANAGED_BY_LABEL`].
+pub const MANAGED_BY: &str = "kuo-operator";
+
+/// Number of changes kept for subscribers which haven't received them yet.
+const CHANGES_CAPACITY: usize = 1024;
+
+/// In-memory stores of the objects created by the operator.
+///
+/// Stores are filled by reflectors watching objects labelled with
+/// `app.kubernetes.io/managed-by=kuo-operator`, so garbage collection
+/// and drift checks don't have to list objects from the API.
+/// Managed users are cached as well, so groups can find their members,
+/// and so are all namespaces, so permissions can be matched against them.
+#[derive(Clone)]
+pub struct OperatorCache {
+    pub users: Store<ManagedUser>,
+    pub namespaces: Store<Namespace>,
+    pub roles: Store<Role>,
+    pub role_bindings: Store<RoleBinding>,
+    pub cluster_roles: Store<ClusterRole>,
+    pub cluster_role_bindings: Store<ClusterRoleBinding>,
+    pub csrs: Store<CertificateSigningRequest>,
+    pub changes: CacheChanges,
+}
+
+/// Channels broadcasting objects changed in the stores, see [`subscribe`].
+#[derive(Clone)]
+pub struct CacheChanges {
+    pub users: broadcast::Sender<Arc<ManagedUser>>,
+    pub namespaces: broadcast::Sender<Arc<Namespace>>,
+    pub roles: broadcast::Sender<Arc<Role>>,
+    pub role_bindings: broadcast::Sender<Arc<RoleBinding>>,
+    pub cluster_roles: broadcast::Sender<Arc<ClusterRole>>,
+    pub cluster_role_bindings: broadcast::Sender<Arc<ClusterRoleBinding>>,
+}
+
+/// Start a reflector of objects in the background.
+///
+/// Applied and deleted objects are broadcast to the returned channel.
+fn reflect<K>(
+    client: &kube::Client,
+    config: watcher::Config,
+) -> (Store<K>, broadcast::Sender<Arc<K>>)
+where
+    K: kube::Resource<DynamicType = ()>
+        + DeserializeOwned
+        + Clone
+        + std::fmt::Debug
+        + Send
+        + Sync
+        + 'static,
+{
+    let (reader, writer) = reflector::store();
+    let (changes, _) = broadcast::channel(CHANGES_CAPACITY);
+    let sender = changes.clone();
+    let stream = watcher(kube::Api::<K>::all(client.clone()), config)
+        .default_backoff()
+        .reflect(writer)
+        .for_each(move |event| {
+            // Sending fails only if nobody is subscribed, so the error is ignored.
+            match event {
+                Ok(watcher::Event::Applied(obj) | watcher::Event::Deleted(obj)) => {
+                    sender.send(Arc::new(obj)).ok();
+                }
+                Ok(watcher::Event::Restarted(objs)) => {
+                    for obj in objs {
+                        sender.send(Arc::new(obj)).ok();
+                    }
+                }
+                Err(err) => tracing::warn!("Cannot watch {}. {err}", K::kind(&())),
+            }
+            futures::future::ready(())
+        });
+    tokio::spawn(stream);
+    (reader, changes)
+}
+
+/// Stream of objects changed in a store, including deleted ones.
+///
+/// Subscribers which fall behind skip the oldest changes.
+pub fn subscribe<K>(changes: &broadcast::Sender<Arc<K>>) -> impl Stream<Item = Arc<K>> + Send
+where
+    K: Send + Sync + 'static,
+{
+    futures::stream::unfold(changes.subscribe(), |mut receiver| async move {
+        loop {
+            match receiver.recv().await {
+                Ok(obj) => return Some((obj, receiver)),
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    tracing::warn!("Skipped {skipped} changes of cached objects");
+                }
+                Err(broadcast::error::RecvError::Closed) => return None,
+            }
+        }
+    })
+}
+
+/// Get objects from the store which have the label with the given value.
+#[must_use]
+pub fn labelled<K>(store: &Store<K>, (key, value): (&str, &str)) -> Vec<Arc<K>>
+where
+    K: kube::Resource<DynamicType = ()> + Clone + 'static,
+{
+    store
+        .state()
+        .into_iter()
+        .filter(|obj| obj.labels().get(key).is_some_and(|v| v == value))
+        .collect()
+}
+
+impl OperatorCache {
+    /// Create stores and start watching the objects.
+    #[must_use]
+    pub fn new(client: &kube::Client) -> Self {
+        let managed =
+            || watcher::Config::default().labels(&format!("{MANAGED_BY_LABEL}={MANAGED_BY}"));
+        let (users, user_changes) = reflect(client, watcher::Config::default());
+        let (namespaces, namespace_changes) = reflect(client, watcher::Config::default());
+        let (roles, role_changes) = reflect(client, managed());
+        let (role_bindings, role_binding_changes) = reflect(client, managed());
+        let (cluster_roles, cluster_role_changes) = reflect(client, managed());
+        let (cluster_role_bindings, cluster_role_binding_changes) = reflect(client, managed());
+        let (csrs, _) = reflect(client, managed());
+        Self {
+            users,
+            namespaces,
+            roles,
+            role_bindings,
+            cluster_roles,
+            cluster_role_bindings,
+            csrs,
+            changes: CacheChanges {
+                users: user_changes,
+                namespaces: namespace_changes,
+                roles: role_changes,
+                role_bindings: role_binding_changes,
+                cluster_roles: cluster_role_changes,
+                cluster_role_bindings: cluster_role_binding_changes,
+            },
+        }
+    }
+
+    /// Wait until all stores have received the initial list of objects.
+    pub async fn wait_until_ready(&self) -> KuoResult<()> {
+        let not_ready = |_| KuoError::CannotReconcile(String::from("Cache has been stopped"));
+        self.users.wait_until_ready().await.map_err(not_ready)?;
+        self.namespaces
+            .wait_until_ready()
+            .await
+            .map_err(not_ready)?;
+        self.roles.wait_until_ready().await.map_err(not_ready)?;
+        self.role_bindings
+            .wait_until_ready()
+            .await
+            .map_err(not_ready)?;
+        self.cluster_roles
+            .wait_until_ready()
+            .await
+            .map_err(not_ready)?;
+        self.cluster_role_bindings
+            .wait_until_ready()
+            .await
+            .map_err(not_ready)?;
+        self.csrs.wait_until_ready().await.map_err(not_ready)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,147 @@
+use std::{sync::Arc, time::Duration};
+
+use chrono::{DateTime, Utc};
+use kube::{
+    runtime::{controller::Action, reflector::Lookup},
+    ResourceExt,
+};
+
+use crate::{
+    crds::{
+        access_request::{AccessRequest, AccessRequestPhase},
+        managed_user::ManagedUser,
+    },
+    operator::{
+        ctx::OperatorCtx,
+        error::{KuoError, KuoResult},
+        utils::resource::patch_status,
+    },
+};
+
+/// Requeue the request after `after` or right after it expires, whichever is sooner.
+fn requeue(expires_at: DateTime<Utc>, after: Duration) -> Action {
+    let until_expiry = (expires_at - Utc::now()).to_std().unwrap_or_default();
+    Action::requeue(after.min(until_expiry + Duration::from_secs(1)))
+}
+
+/// Reconcile the request, modifying its status in place.
+async fn reconcile_request(
+    request: &mut AccessRequest,
+    ctx: Arc<OperatorCtx>,
+) -> KuoResult<Action> {
+    let status = request.status.clone().unwrap_or_default();
+    let expires_at = status
+        .expires_at
+        .as_deref()
+        .map(DateTime::parse_from_rfc3339)
+        .transpose()
+        .map_err(|err| KuoError::CannotReconcile(format!("Invalid expiration time: {err}")))?
+        .map(|expires_at| expires_at.with_timezone(&Utc));
+    let now = Utc::now();
+    if status.phase == AccessRequestPhase::Expired
+        || expires_at.is_some_and(|expires_at| now >= expires_at)
+    {
+        tracing::info!("Access has expired. Removing permissions.");
+        request.revoke(ctx.clone()).await?;
+        let status = request.status_or_default();
+        status.phase = AccessRequestPhase::Expired;
+        status.message = Some(String::from("Access has expired."));
+        status.roles.clear();
+        status.cluster_roles.clear();
+        status.pending_namespaces.clear();
+        status.failed_namespaces.clear();
+        return Ok(Action::await_change());
+    }
+
+    let expires_at = if let Some(expires_at) = expires_at {
+        expires_at
+    } else {
+        if let Some(reason) = request.rejection(ctx.args.access_request_max_duration) {
+            tracing::warn!("Rejecting the request. {reason}");
+            let status = request.status_or_default();
+            status.phase = AccessRequestPhase::Rejected;
+            status.message = Some(reason);
+            return Ok(Action::await_change());
+        }
+        let approvals = request.approvals().len();
+        let required = usize::from(ctx.args.access_request_approvals);
+        if approvals < required {
+            let status = request.status_or_default();
+            status.phase = AccessRequestPhase::Pending;
+            status.message = Some(format!("Waiting for approvals: {approvals}/{required}"));
+            return Ok(Action::await_change());
+        }
+        let expires_at = now + chrono::Duration::seconds(request.spec.duration_seconds);
+        tracing::info!("Request has been approved. Access expires at {expires_at}");
+        let status = request.status_or_default();
+        status.granted_at = Some(now.to_rfc3339());
+        status.expires_at = Some(expires_at.to_rfc3339());
+        expires_at
+    };
+
+    let user = kube::Api::<ManagedUser>::all(ctx.client.clone())
+        .get_opt(&request.spec.user)
+        .await?;
+    let Some(user) = user.filter(ManagedUser::has_access) else {
+        tracing::warn!("User doesn't exist or has no access. Removing permissions.");
+        request.revoke(ctx.clone()).await?;
+        let status = request.status_or_default();
+        status.phase = AccessRequestPhase::Granted;
+        status.message = Some(String::from(
+            "Permissions are removed, because the user doesn't exist or has no access.",
+        ));
+        status.roles.clear();
+        status.cluster_roles.clear();
+        status.pending_namespaces.clear();
+        status.failed_namespaces.clear();
+        return Ok(requeue(expires_at, Duration::from_secs(60 * 10)));
+    };
+    let applied = request.grant(&user, ctx.clone()).await?;
+    let status = request.status_or_default();
+    status.phase = AccessRequestPhase::Granted;
+    status.message = Some(if !applied.failed_namespaces.is_empty() {
+        format!(
+            "Access is granted, but permissions cannot be applied in namespaces: {}",
+            applied.failed_namespaces.join(", ")
+        )
+    } else if !applied.pending_namespaces.is_empty() {
+        format!(
+            "Access is granted. Waiting for namespaces to be created: {}",
+            applied.pending_namespaces.join(", ")
+        )
+    } else {
+        String::from("Access is granted.")
+    });
+    status.roles = applied.roles;
+    status.cluster_roles = applied.cluster_roles;
+    status.pending_namespaces = applied.pending_namespaces;
+    status.failed_namespaces = applied.failed_namespaces;
+    // Failed namespaces are retried sooner.
+    let after = if status.failed_namespaces.is_empty() {
+        Duration::from_secs(60 * 10)
+    } else {
+        Duration::from_secs(60)
+    };
+    Ok(requeue(expires_at, after))
+}
+
+#[tracing::instrument(skip(request, ctx), fields(name = request.name_any()), err)]
+pub async fn reconcile(request: Arc<AccessRequest>, ctx: Arc<OperatorCtx>) -> KuoResult<Action> {
+    if request.name().is_none() {
+        tracing::warn!("Access request metadata has no name");
+        return Err(KuoError::CannotReconcile(String::from(
+            "Access request metadata has no name",
+        )));
+    }
+    let mut request = Arc::unwrap_or_clone(request);
+    let mut previous = request.status.clone();
+    let result = reconcile_request(&mut request, ctx.clone()).await;
+    // Approvals are written by the approvers. They are left out of the patch,
+    // so approvals added during the reconciliation are not overwritten.
+    for status in [&mut previous, &mut request.status].into_iter().flatten() {
+        status.approvals.clear();
+    }
+    // Status is saved even if the reconciliation has failed halfway.
+    patch_status(&ctx.client, &request, previous.as_ref()).await?;
+    result
+}
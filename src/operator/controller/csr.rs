@@ -1,27 +1,29 @@
 use std::{sync::Arc, time::Duration};
 
 use k8s_openapi::{
-    api::{
-        certificates::v1::{
-            CertificateSigningRequest, CertificateSigningRequestCondition,
-            CertificateSigningRequestStatus,
-        },
-        core::v1::Secret,
+    api::certificates::v1::{
+        CertificateSigningRequest, CertificateSigningRequestCondition,
+        CertificateSigningRequestStatus,
     },
     apimachinery::pkg::apis::meta::v1::Time,
 };
 use kube::{
-    api::{DeleteParams, PatchParams},
-    runtime::{controller::Action, reflector::Lookup},
+    api::PatchParams,
+    runtime::{
+        controller::Action,
+        reflector::{Lookup, ObjectRef},
+    },
     ResourceExt,
 };
+use openssl::nid::Nid;
 
 use crate::{
-    crds::managed_user::ManagedUser,
+    crds::managed_user::forbidden_groups,
     operator::{
         ctx::OperatorCtx,
         error::{KuoError, KuoResult},
-        utils::get_kube_cert,
+        issuer::kube_csr::KubeCsrIssuer,
+        utils::cert::name_entries,
     },
 };
 
@@ -73,13 +75,6 @@ async fn approve_csr(csr: &mut CertificateSigningRequest, ctx: Arc<OperatorCtx>)
     Ok(())
 }
 
-async fn delete_csr(ctx: Arc<OperatorCtx>, name: &str) -> KuoResult<()> {
-    kube::Api::<CertificateSigningRequest>::all(ctx.client.clone())
-        .delete(name, &DeleteParams::default())
-        .await?;
-    Ok(())
-}
-
 #[tracing::instrument(skip(csr_arc, ctx), fields(name=csr_arc.name_any()), err)]
 pub async fn reconcile(
     csr_arc: Arc<CertificateSigningRequest>,
@@ -91,58 +86,64 @@ pub async fn reconcile(
         return Err(KuoError::CannotReconcile(String::from(
             "CSR metadata has no name",
         )));
-    };
-    let owners = csr_arc.owner_references();
-    let user = if let Some(owner) = owners.first() {
-        kube::Api::<ManagedUser>::all(ctx.client.clone())
-            .get(&owner.name)
-            .await?
-    } else {
+    }
+    let Some(owner) = csr_arc
+        .owner_references()
+        .iter()
+        .find(|owner| owner.kind == "ManagedUser")
+    else {
         tracing::warn!("No owner found for CSR");
         return Ok(Action::requeue(Duration::from_secs(60 * 5)));
     };
-    let Some(mut users_secret) = user
-        .get_secret(kube::Api::<Secret>::namespaced(
-            ctx.client.clone(),
-            ctx.client.default_namespace(),
-        ))
-        .await?
-    else {
-        return Err(KuoError::CannotReconcile(String::from(
-            "User doesn't have a secret key.",
-        )));
-    };
-    if users_secret.cert.is_some() && users_secret.kubeconfig.is_some() {
-        tracing::debug!("Certificate and kubeconfig exist for the user.");
-        return Ok(Action::requeue(Duration::from_secs(60 * 10)));
-    }
     if let Some(CertificateSigningRequestStatus {
-        certificate: Some(csr_signed_cert),
+        certificate: Some(_),
         conditions: _,
     }) = &csr_arc.status
     {
-        tracing::info!("CSR has been signed. Generating kubeconfig.");
-        let root_kube_cert = get_kube_cert(ctx.clone()).await?;
-        let user_cert = String::from_utf8(csr_signed_cert.0.clone())?;
-        let kubeconfig = serde_yaml::to_string(&user.build_kubeconfig(
-            &ctx.args.kube_addr,
-            &ctx.args.cluster_name,
-            users_secret.pkey.as_str(),
-            &user_cert,
-            &root_kube_cert,
-        ))?;
-        users_secret.kubeconfig = Some(kubeconfig.clone());
-        users_secret.cert = Some(user_cert);
-        user.set_secret(
-            kube::Api::namespaced(ctx.client.clone(), ctx.client.default_namespace()),
-            &users_secret,
-        )
-        .await?;
-        user.send_kubeconfig(ctx.clone(), &kubeconfig).await?;
-        delete_csr(ctx.clone(), csr_arc.name_any().as_str()).await?;
-        return Ok(Action::requeue(Duration::from_secs(60 * 10)));
+        // The certificate is picked up by the managed user controller.
+        tracing::debug!("CSR has been signed.");
+        return Ok(Action::await_change());
+    }
+    if KubeCsrIssuer::failure(&csr_arc).is_some() {
+        // Denied and failed requests are reported by the managed user controller.
+        tracing::warn!("CSR has been denied or has failed.");
+        return Ok(Action::await_change());
+    }
+    // Requests are approved automatically, so they must not grant reserved groups.
+    let request = openssl::x509::X509Req::from_pem(&csr_arc.spec.request.0)?;
+    let groups = name_entries(request.subject_name(), Nid::ORGANIZATIONNAME)?;
+    let forbidden = forbidden_groups(&groups, &ctx.args.allowed_groups);
+    if !forbidden.is_empty() {
+        tracing::warn!(
+            "CSR requests forbidden groups: {}. Not approving it.",
+            forbidden.join(", ")
+        );
+        return Ok(Action::await_change());
+    }
+    // Only the current identity of the user is bound to roles.
+    // The status of the user may lag behind its new request,
+    // so the request is checked again once the user changes.
+    let user = ctx
+        .cache
+        .users
+        .get(&ObjectRef::new(&owner.name))
+        .filter(|user| user.metadata.uid.as_ref() == Some(&owner.uid));
+    let Some(user) = user else {
+        tracing::warn!("Owner of the CSR doesn't exist. Not approving it.");
+        return Ok(Action::await_change());
+    };
+    let identity = user.identity(ctx.args.identity_generations);
+    let common_names = name_entries(request.subject_name(), Nid::COMMONNAME)?;
+    if common_names != [identity.as_str()] {
+        tracing::warn!(
+            "CSR is requested for {}, but the user's identity is {identity}. Not approving it.",
+            common_names.join(", ")
+        );
+        return Ok(Action::await_change());
     }
     let mut csr = Arc::unwrap_or_clone(csr_arc);
+    // The user's status is updated by the managed user controller,
+    // which is triggered by the changes of its CSR.
     approve_csr(&mut csr, ctx.clone()).await?;
     Ok(Action::requeue(Duration::from_secs(60 * 10)))
 }
@@ -0,0 +1,51 @@
+use std::{sync::Arc, time::Duration};
+
+use kube::{
+    runtime::{controller::Action, reflector::Lookup},
+    ResourceExt,
+};
+
+use crate::{
+    crds::managed_group::ManagedGroup,
+    operator::{
+        ctx::OperatorCtx,
+        error::{KuoError, KuoResult},
+        utils::resource::patch_status,
+    },
+};
+
+#[tracing::instrument(skip(group, ctx), fields(name = group.name_any()), err)]
+pub async fn reconcile(group: Arc<ManagedGroup>, ctx: Arc<OperatorCtx>) -> KuoResult<Action> {
+    if group.name().is_none() {
+        tracing::warn!("Managed group metadata has no name");
+        return Err(KuoError::CannotReconcile(String::from(
+            "Managed group metadata has no name",
+        )));
+    }
+    if group.metadata.uid.is_none() {
+        tracing::warn!("Managed group metadata has no UID");
+        return Err(KuoError::CannotReconcile(String::from(
+            "Managed group metadata has no UID",
+        )));
+    }
+    let members = group.members(&ctx.cache.users);
+    tracing::info!("Binding {} members to the group's roles", members.len());
+    let applied = group.apply(&members, ctx.clone()).await?;
+    let mut group = Arc::unwrap_or_clone(group);
+    let previous = group.status.clone();
+    let generation = group.metadata.generation;
+    let status = group.status_or_default();
+    status.observed_generation = generation;
+    status.members = members.iter().map(|user| user.name_any()).collect();
+    status.roles = applied.roles;
+    status.cluster_roles = applied.cluster_roles;
+    status.pending_namespaces = applied.pending_namespaces;
+    status.failed_namespaces = applied.failed_namespaces;
+    let failed = !status.failed_namespaces.is_empty();
+    patch_status(&ctx.client, &group, previous.as_ref()).await?;
+    if failed {
+        return Ok(Action::requeue(Duration::from_secs(60)));
+    }
+    // Access windows of the members may change without any updates.
+    Ok(Action::requeue(Duration::from_secs(60 * 10)))
+}
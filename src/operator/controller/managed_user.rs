@@ -1,75 +1,806 @@
 use std::{sync::Arc, time::Duration};
 
-use k8s_openapi::{
-    api::{certificates::v1::CertificateSigningRequest, core::v1::Secret},
-    ByteString,
-};
+use k8s_openapi::api::core::v1::Secret;
 use kube::{
-    api::{ObjectMeta, PostParams},
-    runtime::{controller::Action, reflector::Lookup},
+    runtime::{
+        controller::Action,
+        events::{Event, EventType},
+        reflector::Lookup,
+    },
     ResourceExt,
 };
+use openssl::nid::Nid;
 
 use crate::{
-    crds::managed_user::{ManagedUser, ManagedUserSecretData},
+    crds::{
+        key_algorithm::KeyAlgorithm,
+        managed_user::{
+            conditions, forbidden_groups, AccessWindow, ManagedUser, ManagedUserSecretData,
+            ManagedUserStatus, REISSUE_ANNOTATION, ROTATE_ANNOTATION,
+        },
+    },
     operator::{
         ctx::OperatorCtx,
         error::{KuoError, KuoResult},
-        utils::meta::ObjectMetaKuoExt,
+        issuer::{CertificateIssuer, IssuanceState},
+        utils::{
+            cert::{cert_common_names, cert_groups, cert_matches_key, name_entries, CertValidity},
+            get_kube_cert,
+            resource::patch_status,
+        },
     },
 };
 
-fn gen_user_pkey() -> KuoResult<openssl::pkey::PKey<openssl::pkey::Private>> {
-    tracing::info!("Generating RSA key");
-    let rsa = openssl::rsa::Rsa::generate(4096)?;
-    Ok(openssl::pkey::PKey::from_rsa(rsa)?)
+async fn gen_user_pkey(
+    algorithm: KeyAlgorithm,
+) -> KuoResult<openssl::pkey::PKey<openssl::pkey::Private>> {
+    tokio::task::spawn_blocking(move || algorithm.generate()).await?
 }
 
 fn build_csr(
     username: &str,
+    groups: &[String],
     pkey: &openssl::pkey::PKey<openssl::pkey::Private>,
 ) -> KuoResult<openssl::x509::X509Req> {
-    tracing::info!("Building Certificate Signing Request (CSR)");
+    tracing::debug!("Building Certificate Signing Request (CSR)");
     let mut req_builder = openssl::x509::X509Req::builder()?;
     let mut x509_name = openssl::x509::X509NameBuilder::new()?;
     x509_name.append_entry_by_text("CN", username)?;
+    for group in groups {
+        x509_name.append_entry_by_text("O", group)?;
+    }
     req_builder.set_subject_name(&x509_name.build())?;
     req_builder.set_pubkey(pkey)?;
-    req_builder.sign(pkey, openssl::hash::MessageDigest::sha256())?;
-    tracing::info!("CSR built successfully");
+    req_builder.sign(pkey, KeyAlgorithm::signature_digest(pkey))?;
+    tracing::debug!("CSR built successfully");
     Ok(req_builder.build())
 }
 
-pub async fn create_kube_csr(
+/// Store the signed certificate in the user's secret
+/// and send the new kubeconfig to the user.
+async fn store_certificate(
     ctx: Arc<OperatorCtx>,
-    user: &ManagedUser,
+    user: &mut ManagedUser,
+    mut users_secret: ManagedUserSecretData,
+    user_cert: String,
+) -> KuoResult<()> {
+    if user.spec.certificate_request.is_some() {
+        // The certificate was issued for the user's own key.
+        users_secret.pkey = None;
+    }
+    let root_kube_cert = get_kube_cert(ctx.clone()).await?;
+    let kubeconfig = serde_yaml::to_string(&user.build_kubeconfig(
+        &ctx.args.kube_addr,
+        &ctx.args.cluster_name,
+        users_secret.pkey.as_deref(),
+        &user_cert,
+        &root_kube_cert,
+    ))?;
+    let validity = CertValidity::from_pem(&user_cert)?;
+    tracing::info!("Certificate expires at {}", validity.not_after);
+    users_secret.kubeconfig = Some(kubeconfig.clone());
+    users_secret.cert = Some(user_cert);
+    users_secret.expiration = Some(validity.not_after.to_rfc3339());
+    user.set_secret(
+        kube::Api::namespaced(ctx.client.clone(), ctx.client.default_namespace()),
+        &users_secret,
+        ctx.args.force_conflicts,
+    )
+    .await?;
+    let status = user.status_or_default();
+    status.cert_expires_at = Some(validity.not_after.to_rfc3339());
+    status.set_condition(conditions::CSR_PENDING, false, "Signed", None);
+    status.set_condition(conditions::CERTIFICATE_ISSUED, true, "Issued", None);
+    status.set_condition(conditions::ISSUANCE_FAILED, false, "Issued", None);
+    let delivered = user.send_kubeconfig(ctx.clone(), &kubeconfig).await?;
+    let status = user.status_or_default();
+    if delivered {
+        status.set_condition(conditions::KUBECONFIG_DELIVERED, true, "EmailSent", None);
+    } else {
+        status.set_condition(
+            conditions::KUBECONFIG_DELIVERED,
+            false,
+            "EmailNotConfigured",
+            Some(String::from(
+                "Kubeconfig is available in the user's secret.",
+            )),
+        );
+    }
+    Ok(())
+}
+
+/// Submit certificate request to the issuer.
+///
+/// If the issuer signs the request right away,
+/// the certificate is stored in the user's secret.
+/// Returns `true` if the request is waiting for a signature.
+async fn submit_csr(
+    ctx: Arc<OperatorCtx>,
+    user: &mut ManagedUser,
+    users_secret: ManagedUserSecretData,
     x509_req: &openssl::x509::X509Req,
-    csr_name: &str,
-) -> KuoResult<CertificateSigningRequest> {
-    let mut meta = ObjectMeta::default();
-    meta.insert_label("app.kubernetes.io/managed-by", "kuo-operator");
-    meta.name = Some(csr_name.to_string());
-    meta.add_owner(user);
-    let sign_req = kube::Api::<CertificateSigningRequest>::all(ctx.client.clone())
-        .create(
-            &PostParams::default(),
-            &CertificateSigningRequest {
-                metadata: meta,
-                spec: k8s_openapi::api::certificates::v1::CertificateSigningRequestSpec {
-                    request: ByteString(x509_req.to_pem()?),
-                    signer_name: ctx.args.signer_name.clone(),
-                    usages: Some(vec![
-                        String::from("digital signature"),
-                        String::from("key encipherment"),
-                        String::from("client auth"),
-                    ]),
-                    ..Default::default()
-                },
-                status: None,
+) -> KuoResult<bool> {
+    match ctx.issuer.submit(user, x509_req).await {
+        Err(KuoError::KubeError(kube::Error::Api(err))) if err.code == 409 => {
+            tracing::warn!("Found a stale certificate request. Replacing it.");
+            ctx.issuer.revoke(user).await?;
+            ctx.issuer.submit(user, x509_req).await?;
+        }
+        result => result?,
+    }
+    // Cached requests may still be the replaced ones,
+    // so only certificates issued for this request are stored.
+    if let IssuanceState::Issued(cert) = ctx.issuer.poll(user).await? {
+        if cert_fits(&cert, x509_req, user, ctx.args.identity_generations)? {
+            store_certificate(ctx.clone(), user, users_secret, cert).await?;
+            ctx.issuer.revoke(user).await?;
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+/// Get the value of the annotation if it differs from the last handled one.
+fn unhandled_annotation(
+    user: &ManagedUser,
+    annotation: &str,
+    handled: impl FnOnce(&ManagedUserStatus) -> Option<&String>,
+) -> Option<String> {
+    let requested = user.annotations().get(annotation)?;
+    let handled = user.status.as_ref().and_then(handled);
+    (handled != Some(requested)).then(|| requested.clone())
+}
+
+/// Record denied or failed certificate request in the user's status.
+///
+/// The event is published only once for every failure.
+async fn record_issuance_failure(
+    ctx: Arc<OperatorCtx>,
+    user: &mut ManagedUser,
+    condition: &str,
+    reason: &str,
+    message: &str,
+    has_cert: bool,
+) -> KuoResult<()> {
+    let note = if message.is_empty() {
+        String::from(reason)
+    } else {
+        format!("{reason}: {message}")
+    };
+    tracing::warn!("Certificate request is {condition}. {note}");
+    let recorded = user.status.as_ref().is_some_and(|status| {
+        status.conditions.iter().any(|c| {
+            c.type_ == conditions::ISSUANCE_FAILED
+                && c.status == "True"
+                && c.message.as_ref() == Some(&note)
+        })
+    });
+    let status = user.status_or_default();
+    status.set_condition(conditions::CSR_PENDING, false, condition, None);
+    status.set_condition(
+        conditions::ISSUANCE_FAILED,
+        true,
+        condition,
+        Some(note.clone()),
+    );
+    if !has_cert {
+        status.set_condition(conditions::CERTIFICATE_ISSUED, false, condition, None);
+    }
+    if !recorded {
+        user.publish_event(
+            ctx,
+            Event {
+                type_: EventType::Warning,
+                reason: format!("Certificate{condition}"),
+                note: Some(note),
+                action: String::from("IssueCertificate"),
+                secondary: None,
+            },
+        )
+        .await?;
+    }
+    Ok(())
+}
+
+/// Parse and validate the certificate request provided by the user.
+///
+/// Returns `None` if the user hasn't provided any request.
+fn provided_csr(
+    user: &ManagedUser,
+    identity_generations: bool,
+) -> KuoResult<Option<openssl::x509::X509Req>> {
+    let Some(pem) = &user.spec.certificate_request else {
+        return Ok(None);
+    };
+    let invalid = |reason: &str| KuoError::InvalidCertificateRequest(String::from(reason));
+    let req = openssl::x509::X509Req::from_pem(pem.as_bytes())
+        .map_err(|_| invalid("Cannot parse PEM-encoded request"))?;
+    if !req.verify(req.public_key()?.as_ref())? {
+        return Err(invalid("Signature of the request is invalid"));
+    }
+    let common_names = name_entries(req.subject_name(), Nid::COMMONNAME)?;
+    if common_names != [user.identity(identity_generations)] {
+        return Err(invalid("CN must be equal to the identity of the user"));
+    }
+    let mut groups = name_entries(req.subject_name(), Nid::ORGANIZATIONNAME)?;
+    groups.sort();
+    if groups != user.groups() {
+        return Err(invalid("O entries must be equal to the groups of the user"));
+    }
+    Ok(Some(req))
+}
+
+/// Issuance phase of the user's certificate.
+///
+/// The phase is derived on every reconcile from the user's secret
+/// and the outstanding request, so issuance that was interrupted
+/// at any step is detected and driven to completion.
+enum Phase {
+    /// There's no outstanding request and the user has no certificate.
+    Unrequested,
+    /// Outstanding request for the user's current key is waiting for a signature.
+    Pending,
+    /// Outstanding request has been signed. Contains PEM-encoded certificate.
+    Signed(String),
+    /// Outstanding request has been denied or has failed.
+    Failed {
+        condition: String,
+        reason: String,
+        message: String,
+    },
+    /// Outstanding request was made for another key, identity or groups.
+    Stale,
+    /// User has a certificate and there's no outstanding request.
+    Issued(String),
+}
+
+impl Phase {
+    async fn detect(
+        issuer: &dyn CertificateIssuer,
+        identity_generations: bool,
+        user: &ManagedUser,
+        secret_data: &ManagedUserSecretData,
+        csr: &openssl::x509::X509Req,
+    ) -> KuoResult<Self> {
+        let phase = match issuer.poll(user).await? {
+            IssuanceState::Issued(cert) => {
+                // Cached requests may outlive their revocation,
+                // so a certificate which has been stored already isn't handled again.
+                if secret_data.cert.as_ref() == Some(&cert) {
+                    Self::Issued(cert)
+                } else if cert_fits(&cert, csr, user, identity_generations)? {
+                    Self::Signed(cert)
+                } else {
+                    Self::Stale
+                }
+            }
+            IssuanceState::Pending(request) => {
+                if request_matches(&request, csr)? {
+                    Self::Pending
+                } else {
+                    Self::Stale
+                }
+            }
+            IssuanceState::Failed {
+                condition,
+                reason,
+                message,
+            } => Self::Failed {
+                condition,
+                reason,
+                message,
+            },
+            IssuanceState::NotFound => secret_data
+                .cert
+                .clone()
+                .map_or(Self::Unrequested, Self::Issued),
+        };
+        Ok(phase)
+    }
+}
+
+/// Check whether the certificate has been issued for the request's key
+/// and the user's current groups and identity.
+fn cert_fits(
+    cert: &str,
+    csr: &openssl::x509::X509Req,
+    user: &ManagedUser,
+    identity_generations: bool,
+) -> KuoResult<bool> {
+    Ok(cert_matches_key(cert, csr.public_key()?.as_ref())?
+        && cert_groups(cert)? == user.groups()
+        && cert_common_names(cert)? == [user.identity(identity_generations)])
+}
+
+/// Check whether the outstanding request was made for the same key and subject.
+fn request_matches(request: &str, csr: &openssl::x509::X509Req) -> KuoResult<bool> {
+    let Ok(request) = openssl::x509::X509Req::from_pem(request.as_bytes()) else {
+        return Ok(false);
+    };
+    Ok(request.public_key()?.public_eq(csr.public_key()?.as_ref())
+        && request.subject_name().try_cmp(csr.subject_name())? == std::cmp::Ordering::Equal)
+}
+
+/// Build the certificate request for the key stored in the user's secret.
+///
+/// Returns `None` if there's no key, it cannot be parsed
+/// or it was generated with an algorithm other than `algorithm`.
+fn stored_key_csr(
+    user: &ManagedUser,
+    identity: &str,
+    algorithm: KeyAlgorithm,
+    secret_data: &ManagedUserSecretData,
+) -> KuoResult<Option<openssl::x509::X509Req>> {
+    let Some(pkey_data) = &secret_data.pkey else {
+        return Ok(None);
+    };
+    let Ok(pkey) = openssl::pkey::PKey::private_key_from_pem(pkey_data.as_bytes()) else {
+        tracing::warn!("Cannot parse user's private key. Generating a new one.");
+        return Ok(None);
+    };
+    if KeyAlgorithm::of_key(&pkey) != Some(algorithm) {
+        tracing::info!("User's key algorithm has changed to {algorithm}. Generating a new key.");
+        return Ok(None);
+    }
+    Ok(Some(build_csr(identity, &user.groups(), &pkey)?))
+}
+
+/// Generation of a new key.
+///
+/// Generations are based on time, so they don't repeat after the user is recreated
+/// or its status is lost, and they keep increasing if the clock goes back.
+fn next_generation(previous: Option<i64>, now: chrono::DateTime<chrono::Utc>) -> i64 {
+    let now = now.timestamp();
+    previous.map_or(now, |previous| now.max(previous + 1))
+}
+
+/// Store a new key for the user.
+///
+/// If the user has provided their own request, it's used instead of generating a key.
+/// Otherwise, the credential generation is advanced and permissions are bound
+/// to the new identity, so certificates of older generations lose access.
+/// The secret doesn't contain any certificate afterwards.
+async fn new_key(
+    ctx: Arc<OperatorCtx>,
+    user: &mut ManagedUser,
+    provided_csr: Option<openssl::x509::X509Req>,
+) -> KuoResult<(ManagedUserSecretData, openssl::x509::X509Req)> {
+    let (csr, users_secret_data) = if let Some(csr) = provided_csr {
+        (csr, ManagedUserSecretData::default())
+    } else {
+        let status = user.status_or_default();
+        status.credential_generation = Some(next_generation(
+            status.credential_generation,
+            chrono::Utc::now(),
+        ));
+        let identity = user.identity(ctx.args.identity_generations);
+        if ctx.args.identity_generations {
+            tracing::info!("Binding permissions to the new identity {identity}");
+            user.sync_permissions(ctx.clone()).await?;
+        }
+        user.status_or_default().identity = Some(identity.clone());
+        let algorithm = user.key_algorithm(ctx.args.key_algorithm);
+        let pkey = gen_user_pkey(algorithm).await?;
+        let csr = build_csr(&identity, &user.groups(), &pkey)?;
+        let pkey_data = String::from_utf8(pkey.private_key_to_pem_pkcs8()?).unwrap();
+        let users_secret_data = ManagedUserSecretData {
+            pkey: Some(pkey_data),
+            algorithm: Some(algorithm),
+            ..ManagedUserSecretData::default()
+        };
+        (csr, users_secret_data)
+    };
+    user.set_secret(
+        kube::Api::<Secret>::namespaced(ctx.client.clone(), ctx.client.default_namespace()),
+        &users_secret_data,
+        ctx.args.force_conflicts,
+    )
+    .await?;
+    Ok((users_secret_data, csr))
+}
+
+/// Request a new certificate for the user if the current one is about to expire,
+/// if the user's groups or identity don't match the ones in the certificate
+/// or if the certificate was issued for another key.
+///
+/// The new certificate is issued for the same private key,
+/// so the user's kubeconfig keeps working until the CSR gets signed.
+/// Returns `true` if the renewal is in progress.
+async fn renew_cert_if_needed(
+    ctx: Arc<OperatorCtx>,
+    user: &mut ManagedUser,
+    secret_data: &ManagedUserSecretData,
+    csr: &openssl::x509::X509Req,
+    cert: &str,
+    validity: &CertValidity,
+) -> KuoResult<bool> {
+    let key_changed = !cert_matches_key(cert, csr.public_key()?.as_ref())?;
+    let groups_changed = cert_groups(cert)? != user.groups();
+    let identity_changed =
+        cert_common_names(cert)? != [user.identity(ctx.args.identity_generations)];
+    // Certificates may outlive `validUntil` by the minimal lifetime
+    // Kubernetes signs certificates for.
+    let outlives_access = user
+        .spec
+        .valid_until
+        .is_some_and(|until| validity.not_after > until + chrono::Duration::seconds(600));
+    let expiring = !covers_access(user, validity)
+        && validity.needs_renewal(ctx.args.cert_renew_before_percent);
+    if !key_changed && !groups_changed && !identity_changed && !outlives_access && !expiring {
+        return Ok(false);
+    }
+    if key_changed {
+        tracing::info!("User's key has changed. Requesting a new certificate.");
+    } else if groups_changed {
+        tracing::info!("User's groups have changed. Requesting a new certificate.");
+    } else if identity_changed {
+        tracing::info!("User's identity has changed. Requesting a new certificate.");
+    } else if outlives_access {
+        tracing::info!("Certificate outlives the user's access. Requesting a new certificate.");
+    } else {
+        tracing::info!(
+            "Certificate expires at {}. Requesting a new one.",
+            validity.not_after
+        );
+    }
+    submit_csr(ctx, user, secret_data.clone(), csr).await
+}
+
+/// Check whether the certificate is valid until the user's access ends.
+/// There's no need to renew such certificates.
+fn covers_access(user: &ManagedUser, validity: &CertValidity) -> bool {
+    user.spec
+        .valid_until
+        .is_some_and(|until| validity.not_after >= until)
+}
+
+/// Time until the issued certificate has to be checked again.
+///
+/// Certificates covering the user's access are never renewed, so their renewal time
+/// is ignored. Otherwise a renewal time in the past would requeue the user immediately.
+fn until_next_check(
+    user: &ManagedUser,
+    validity: &CertValidity,
+    renew_before_percent: u8,
+    now: chrono::DateTime<chrono::Utc>,
+) -> Duration {
+    let max = Duration::from_secs(60 * 10);
+    if covers_access(user, validity) {
+        return max;
+    }
+    let until_renewal = (validity.renew_at(renew_before_percent) - now)
+        .to_std()
+        .unwrap_or_default();
+    until_renewal.clamp(Duration::from_secs(60), max)
+}
+
+/// Time until the user's access window starts or ends.
+fn until_access_boundary(user: &ManagedUser) -> Option<Duration> {
+    let now = chrono::Utc::now();
+    let boundary = user.next_access_boundary(now)?;
+    // Reconcile right after the boundary is crossed.
+    Some((boundary - now).to_std().unwrap_or_default() + Duration::from_secs(1))
+}
+
+/// Requeue the user after the given duration,
+/// or earlier if the user's access window starts or ends before that.
+fn requeue(user: &ManagedUser, after: Duration) -> Action {
+    Action::requeue(until_access_boundary(user).map_or(after, |boundary| boundary.min(after)))
+}
+
+/// Wait for changes of the user or for the next boundary of its access window.
+fn await_change(user: &ManagedUser) -> Action {
+    until_access_boundary(user).map_or_else(Action::await_change, Action::requeue)
+}
+
+/// Keep the issued certificate up to date.
+async fn reconcile_issued(
+    ctx: Arc<OperatorCtx>,
+    user: &mut ManagedUser,
+    secret_data: &ManagedUserSecretData,
+    csr: &openssl::x509::X509Req,
+    cert: &str,
+) -> KuoResult<Action> {
+    let validity = CertValidity::from_pem(cert)?;
+    let status = user.status_or_default();
+    status.cert_expires_at = Some(validity.not_after.to_rfc3339());
+    status.set_condition(conditions::CERTIFICATE_ISSUED, true, "Issued", None);
+    let renewing =
+        renew_cert_if_needed(ctx.clone(), user, secret_data, csr, cert, &validity).await?;
+    let status = user.status_or_default();
+    if renewing {
+        status.set_condition(conditions::CSR_PENDING, true, "Renewing", None);
+    } else {
+        status.set_condition(conditions::CSR_PENDING, false, "Signed", None);
+    }
+    if renewing {
+        return Ok(requeue(user, Duration::from_secs(60 * 5)));
+    }
+    let after = until_next_check(
+        user,
+        &validity,
+        ctx.args.cert_renew_before_percent,
+        chrono::Utc::now(),
+    );
+    Ok(requeue(user, after))
+}
+
+/// Reconcile the user, modifying its status in place.
+#[allow(clippy::too_many_lines)]
+async fn reconcile_user(user: &mut ManagedUser, ctx: Arc<OperatorCtx>) -> KuoResult<Action> {
+    let sync_result = user.sync_permissions(ctx.clone()).await;
+    let generation = user.metadata.generation;
+    let identity = user.identity(ctx.args.identity_generations);
+    let disabled = user.is_disabled();
+    let has_access = user.has_access();
+    let access_window = user.access_window(chrono::Utc::now());
+    {
+        let status = user.status_or_default();
+        status.observed_generation = generation;
+        status.identity = Some(identity);
+        if disabled {
+            status.set_condition(conditions::DISABLED, true, "Disabled", None);
+        } else {
+            status.set_condition(conditions::DISABLED, false, "Enabled", None);
+        }
+        match access_window {
+            AccessWindow::NotYetValid => {
+                status.set_condition(conditions::ACCESS_VALID, false, "NotYetValid", None);
+            }
+            AccessWindow::Active => {
+                status.set_condition(conditions::ACCESS_VALID, true, "WithinWindow", None);
+            }
+            AccessWindow::Expired => {
+                status.set_condition(conditions::ACCESS_VALID, false, "Expired", None);
+            }
+        }
+        match &sync_result {
+            Ok(applied) => {
+                status.roles.clone_from(&applied.roles);
+                status.cluster_roles.clone_from(&applied.cluster_roles);
+                status.role_bindings.clone_from(&applied.role_bindings);
+                status
+                    .cluster_role_bindings
+                    .clone_from(&applied.cluster_role_bindings);
+                if disabled {
+                    status.set_condition(
+                        conditions::PERMISSIONS_SYNCED,
+                        false,
+                        "Disabled",
+                        Some(String::from(
+                            "Permissions are removed while the user is disabled.",
+                        )),
+                    );
+                } else if !has_access {
+                    status.set_condition(
+                        conditions::PERMISSIONS_SYNCED,
+                        false,
+                        "OutsideAccessWindow",
+                        Some(String::from(
+                            "Permissions are removed outside of the user's access window.",
+                        )),
+                    );
+                } else if !applied.failed_namespaces.is_empty() {
+                    status.set_condition(
+                        conditions::PERMISSIONS_SYNCED,
+                        false,
+                        "NamespacesFailed",
+                        Some(format!(
+                            "Cannot apply permissions in namespaces: {}",
+                            applied.failed_namespaces.join(", ")
+                        )),
+                    );
+                } else if !applied.pending_namespaces.is_empty() {
+                    status.set_condition(
+                        conditions::PERMISSIONS_SYNCED,
+                        false,
+                        "NamespacesPending",
+                        Some(format!(
+                            "Waiting for namespaces to be created: {}",
+                            applied.pending_namespaces.join(", ")
+                        )),
+                    );
+                } else {
+                    status.set_condition(conditions::PERMISSIONS_SYNCED, true, "Synced", None);
+                }
+            }
+            Err(err) => {
+                status.set_condition(
+                    conditions::PERMISSIONS_SYNCED,
+                    false,
+                    "SyncFailed",
+                    Some(err.to_string()),
+                );
+            }
+        }
+    }
+    let applied = sync_result?;
+    if !applied.reverted.is_empty() {
+        user.publish_event(
+            ctx.clone(),
+            Event {
+                type_: EventType::Normal,
+                reason: String::from("DriftReverted"),
+                note: Some(format!(
+                    "Reverted manual changes of {}",
+                    applied.reverted.join(", ")
+                )),
+                action: String::from("SyncPermissions"),
+                secondary: None,
             },
         )
         .await?;
-    Ok(sign_req)
+    }
+    if !has_access {
+        // Certificates aren't issued until the user has access again.
+        return Ok(await_change(user));
+    }
+    let forbidden = forbidden_groups(&user.groups(), &ctx.args.allowed_groups);
+    if !forbidden.is_empty() {
+        let message = format!("Groups are not allowed: {}", forbidden.join(", "));
+        tracing::warn!("{message}");
+        user.status_or_default().set_condition(
+            conditions::CERTIFICATE_ISSUED,
+            false,
+            "ForbiddenGroups",
+            Some(message),
+        );
+        return Ok(await_change(user));
+    }
+    let provided_csr = match provided_csr(user, ctx.args.identity_generations) {
+        Ok(csr) => csr,
+        Err(KuoError::InvalidCertificateRequest(reason)) => {
+            tracing::warn!("Invalid certificate request: {reason}");
+            user.status_or_default().set_condition(
+                conditions::KEY_GENERATED,
+                false,
+                "InvalidCertificateRequest",
+                Some(reason),
+            );
+            return Ok(await_change(user));
+        }
+        Err(err) => return Err(err),
+    };
+    let key_reason = if provided_csr.is_some() {
+        "ProvidedByUser"
+    } else {
+        "Generated"
+    };
+    let users_secret = user
+        .get_secret(kube::Api::<Secret>::namespaced(
+            ctx.client.clone(),
+            ctx.client.default_namespace(),
+        ))
+        .await?;
+    let byok = provided_csr.is_some();
+    let (mut secret_data, mut csr) = match (users_secret, provided_csr) {
+        (Some(data), Some(csr)) => (data, csr),
+        (None, Some(csr)) => new_key(ctx.clone(), user, Some(csr)).await?,
+        (data, None) => {
+            // If the user stopped providing their own requests
+            // or the key is broken, we need to generate a key for them.
+            // A key without a generation is replaced as well,
+            // since older certificates could be issued for the plain name.
+            let generation_lost = ctx.args.identity_generations
+                && user
+                    .status
+                    .as_ref()
+                    .and_then(|status| status.credential_generation)
+                    .is_none();
+            let csr = data
+                .as_ref()
+                .filter(|_| !generation_lost)
+                .map(|data| {
+                    stored_key_csr(
+                        user,
+                        &user.identity(ctx.args.identity_generations),
+                        user.key_algorithm(ctx.args.key_algorithm),
+                        data,
+                    )
+                })
+                .transpose()?
+                .flatten();
+            match (data, csr) {
+                (Some(data), Some(csr)) => (data, csr),
+                _ => new_key(ctx.clone(), user, None).await?,
+            }
+        }
+    };
+    user.status_or_default()
+        .set_condition(conditions::KEY_GENERATED, true, key_reason, None);
+
+    if let Some(rotation) =
+        unhandled_annotation(user, ROTATE_ANNOTATION, |s| s.last_rotation.as_ref())
+    {
+        if byok {
+            tracing::warn!(
+                "User provides their own certificate request. Update it to rotate the key."
+            );
+        } else {
+            tracing::info!("Rotating user's credentials");
+            ctx.issuer.revoke(user).await?;
+            (secret_data, csr) = new_key(ctx.clone(), user, None).await?;
+        }
+        let status = user.status_or_default();
+        status.last_rotation = Some(rotation);
+        if !byok {
+            status.cert_expires_at = None;
+            status.set_condition(conditions::CERTIFICATE_ISSUED, false, "Rotating", None);
+            status.set_condition(conditions::ISSUANCE_FAILED, false, "Rotated", None);
+        }
+    }
+
+    let phase = Phase::detect(
+        ctx.issuer.as_ref(),
+        ctx.args.identity_generations,
+        user,
+        &secret_data,
+        &csr,
+    )
+    .await?;
+    let mut reissue = unhandled_annotation(user, REISSUE_ANNOTATION, |s| s.last_reissue.as_ref());
+    if !matches!(phase, Phase::Failed { .. }) {
+        if let Some(reissue) = reissue.take() {
+            tracing::info!("Certificate request hasn't failed. Ignoring re-request.");
+            user.status_or_default().last_reissue = Some(reissue);
+        }
+    }
+    match phase {
+        Phase::Signed(cert) => {
+            tracing::info!("Certificate has been issued. Generating kubeconfig.");
+            store_certificate(ctx.clone(), user, secret_data, cert).await?;
+            ctx.issuer.revoke(user).await?;
+            return Ok(requeue(user, Duration::from_secs(60 * 10)));
+        }
+        Phase::Pending => {
+            let renewing = secret_data.cert.is_some();
+            let status = user.status_or_default();
+            if renewing {
+                status.set_condition(conditions::CSR_PENDING, true, "Renewing", None);
+            } else {
+                status.set_condition(conditions::CSR_PENDING, true, "WaitingForSignature", None);
+                status.set_condition(conditions::CERTIFICATE_ISSUED, false, "Pending", None);
+            }
+            return Ok(requeue(user, Duration::from_secs(60 * 5)));
+        }
+        Phase::Issued(cert) => {
+            return reconcile_issued(ctx, user, &secret_data, &csr, &cert).await;
+        }
+        Phase::Failed {
+            condition,
+            reason,
+            message,
+        } => {
+            let Some(reissue) = reissue else {
+                let has_cert = secret_data.cert.is_some();
+                record_issuance_failure(ctx.clone(), user, &condition, &reason, &message, has_cert)
+                    .await?;
+                // Retrying won't help until the user asks for a new certificate.
+                return Ok(await_change(user));
+            };
+            tracing::info!("Re-requesting certificate with a new key");
+            ctx.issuer.revoke(user).await?;
+            (secret_data, csr) = new_key(ctx.clone(), user, byok.then_some(csr)).await?;
+            let status = user.status_or_default();
+            status.last_reissue = Some(reissue);
+            status.set_condition(conditions::ISSUANCE_FAILED, false, "Reissued", None);
+        }
+        Phase::Stale => {
+            tracing::info!("Outstanding certificate request is stale. Replacing it.");
+            ctx.issuer.revoke(user).await?;
+        }
+        Phase::Unrequested => {}
+    }
+
+    let has_cert = secret_data.cert.is_some();
+    if submit_csr(ctx.clone(), user, secret_data, &csr).await? {
+        let status = user.status_or_default();
+        status.set_condition(conditions::CSR_PENDING, true, "Created", None);
+        if !has_cert {
+            status.set_condition(conditions::CERTIFICATE_ISSUED, false, "Pending", None);
+        }
+    }
+    Ok(requeue(user, Duration::from_secs(60 * 5)))
 }
 
 #[tracing::instrument(skip(user, ctx), fields(username = user.name_any()), err)]
@@ -79,38 +810,262 @@ pub async fn reconcile(user: Arc<ManagedUser>, ctx: Arc<OperatorCtx>) -> KuoResu
         return Err(KuoError::CannotReconcile(String::from(
             "Managed user metadata has no name",
         )));
-    };
+    }
     if user.metadata.uid.is_none() {
         tracing::warn!("Managed user metadata has no UID");
         return Err(KuoError::CannotReconcile(String::from(
             "Managed user metadata has no UID",
         )));
     }
-    user.sync_permissions(ctx.clone()).await?;
-    let users_secret = user
-        .get_secret(kube::Api::<Secret>::namespaced(
-            ctx.client.clone(),
-            ctx.client.default_namespace(),
-        ))
-        .await?;
-    if users_secret.is_some() {
-        return Ok(Action::requeue(Duration::from_secs(60 * 10)));
-    }
-    let pkey = gen_user_pkey()?;
-    let username = user.name_any();
-    let csr = build_csr(&username, &pkey)?;
-    let csr_name = format!("kuo-{}", &username);
-    let pkey_data = String::from_utf8(pkey.private_key_to_pem_pkcs8()?).unwrap();
-    let users_secret_data = ManagedUserSecretData {
-        pkey: pkey_data,
-        ..ManagedUserSecretData::default()
+    let mut user = Arc::unwrap_or_clone(user);
+    let previous = user.status.clone();
+    let result = reconcile_user(&mut user, ctx.clone()).await;
+    // Status is saved even if the reconciliation has failed halfway.
+    user.status_or_default().refresh_ready();
+    patch_status(&ctx.client, &user, previous.as_ref()).await?;
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use openssl::{
+        asn1::Asn1Time,
+        hash::MessageDigest,
+        pkey::{PKey, Private},
+        x509::{X509NameBuilder, X509Req, X509},
     };
-    user.set_secret(
-        kube::Api::<Secret>::namespaced(ctx.client.clone(), ctx.client.default_namespace()),
-        &users_secret_data,
-    )
-    .await?;
 
-    create_kube_csr(ctx, &user, &csr, &csr_name).await?;
-    Ok(Action::requeue(Duration::from_secs(60 * 5)))
+    use crate::{
+        crds::{
+            key_algorithm::KeyAlgorithm,
+            managed_user::{ManagedUser, ManagedUserCRD, ManagedUserSecretData},
+        },
+        operator::{
+            issuer::{local_ca::LocalCa, memory::MemoryIssuer, CertificateIssuer, IssuanceState},
+            utils::cert::CertValidity,
+        },
+    };
+
+    use std::time::Duration;
+
+    use super::{build_csr, cert_fits, next_generation, until_next_check, Phase};
+
+    fn test_ca() -> LocalCa {
+        let key = KeyAlgorithm::EcdsaP256.generate().unwrap();
+        let mut name = X509NameBuilder::new().unwrap();
+        name.append_entry_by_text("CN", "kuo-test-ca").unwrap();
+        let name = name.build();
+        let mut builder = X509::builder().unwrap();
+        builder.set_version(2).unwrap();
+        builder.set_subject_name(&name).unwrap();
+        builder.set_issuer_name(&name).unwrap();
+        builder.set_pubkey(&key).unwrap();
+        builder
+            .set_not_before(Asn1Time::days_from_now(0).unwrap().as_ref())
+            .unwrap();
+        builder
+            .set_not_after(Asn1Time::days_from_now(1).unwrap().as_ref())
+            .unwrap();
+        builder.sign(&key, MessageDigest::sha256()).unwrap();
+        LocalCa::new(builder.build(), key)
+    }
+
+    fn user(groups: &[&str]) -> ManagedUser {
+        ManagedUser::new(
+            "alice",
+            ManagedUserCRD {
+                groups: Some(groups.iter().map(|group| String::from(*group)).collect()),
+                ..Default::default()
+            },
+        )
+    }
+
+    fn request(user: &ManagedUser) -> (PKey<Private>, X509Req) {
+        let key = KeyAlgorithm::EcdsaP256.generate().unwrap();
+        let csr = build_csr("alice", &user.groups(), &key).unwrap();
+        (key, csr)
+    }
+
+    fn sign(csr: &X509Req) -> String {
+        String::from_utf8(test_ca().sign(csr, 3600).unwrap().to_pem().unwrap()).unwrap()
+    }
+
+    async fn detect(
+        issuer: &MemoryIssuer,
+        user: &ManagedUser,
+        secret_data: &ManagedUserSecretData,
+        csr: &X509Req,
+    ) -> Phase {
+        Phase::detect(issuer, false, user, secret_data, csr)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn unrequested_without_request_and_certificate() {
+        let user = user(&[]);
+        let (_, csr) = request(&user);
+        let phase = detect(
+            &MemoryIssuer::default(),
+            &user,
+            &ManagedUserSecretData::default(),
+            &csr,
+        )
+        .await;
+        assert!(matches!(phase, Phase::Unrequested));
+    }
+
+    #[tokio::test]
+    async fn issued_when_only_the_secret_has_a_certificate() {
+        let user = user(&[]);
+        let (_, csr) = request(&user);
+        let secret_data = ManagedUserSecretData {
+            cert: Some(sign(&csr)),
+            ..Default::default()
+        };
+        let phase = detect(&MemoryIssuer::default(), &user, &secret_data, &csr).await;
+        assert!(matches!(phase, Phase::Issued(_)));
+    }
+
+    #[tokio::test]
+    async fn pending_for_the_current_key() {
+        let user = user(&["developers"]);
+        let (_, csr) = request(&user);
+        let issuer = MemoryIssuer::default();
+        issuer.submit(&user, &csr).await.unwrap();
+        let phase = detect(&issuer, &user, &ManagedUserSecretData::default(), &csr).await;
+        assert!(matches!(phase, Phase::Pending));
+    }
+
+    #[tokio::test]
+    async fn stale_for_another_key() {
+        let user = user(&["developers"]);
+        let (_, old_csr) = request(&user);
+        let (_, csr) = request(&user);
+        let issuer = MemoryIssuer::default();
+        issuer.submit(&user, &old_csr).await.unwrap();
+        let phase = detect(&issuer, &user, &ManagedUserSecretData::default(), &csr).await;
+        assert!(matches!(phase, Phase::Stale));
+    }
+
+    #[tokio::test]
+    async fn signed_for_a_matching_certificate() {
+        let user = user(&["developers"]);
+        let (_, csr) = request(&user);
+        let cert = sign(&csr);
+        let issuer = MemoryIssuer::default();
+        issuer.set_state("alice", IssuanceState::Issued(cert.clone()));
+        let phase = detect(&issuer, &user, &ManagedUserSecretData::default(), &csr).await;
+        assert!(matches!(phase, Phase::Signed(signed) if signed == cert));
+    }
+
+    #[tokio::test]
+    async fn issued_when_the_signed_certificate_is_stored() {
+        let user = user(&["developers"]);
+        let (_, csr) = request(&user);
+        let cert = sign(&csr);
+        let issuer = MemoryIssuer::default();
+        issuer.set_state("alice", IssuanceState::Issued(cert.clone()));
+        let secret_data = ManagedUserSecretData {
+            cert: Some(cert.clone()),
+            ..Default::default()
+        };
+        let phase = detect(&issuer, &user, &secret_data, &csr).await;
+        assert!(matches!(phase, Phase::Issued(stored) if stored == cert));
+    }
+
+    #[tokio::test]
+    async fn stale_for_a_certificate_with_other_groups() {
+        let user_before = user(&["developers"]);
+        let (key, old_csr) = request(&user_before);
+        let cert = sign(&old_csr);
+        let user = user(&["developers", "on-call"]);
+        let csr = build_csr("alice", &user.groups(), &key).unwrap();
+        let issuer = MemoryIssuer::default();
+        issuer.set_state("alice", IssuanceState::Issued(cert));
+        let phase = detect(&issuer, &user, &ManagedUserSecretData::default(), &csr).await;
+        assert!(matches!(phase, Phase::Stale));
+    }
+
+    #[tokio::test]
+    async fn failed_when_the_request_is_denied() {
+        let user = user(&[]);
+        let (_, csr) = request(&user);
+        let issuer = MemoryIssuer::default();
+        issuer.set_state(
+            "alice",
+            IssuanceState::Failed {
+                condition: String::from("Denied"),
+                reason: String::from("NotAllowed"),
+                message: String::new(),
+            },
+        );
+        let phase = detect(&issuer, &user, &ManagedUserSecretData::default(), &csr).await;
+        assert!(matches!(phase, Phase::Failed { condition, .. } if condition == "Denied"));
+    }
+
+    #[tokio::test]
+    async fn unrequested_after_the_request_is_revoked() {
+        let user = user(&[]);
+        let (_, csr) = request(&user);
+        let issuer = MemoryIssuer::default();
+        issuer.submit(&user, &csr).await.unwrap();
+        issuer.revoke(&user).await.unwrap();
+        let phase = detect(&issuer, &user, &ManagedUserSecretData::default(), &csr).await;
+        assert!(matches!(phase, Phase::Unrequested));
+    }
+
+    #[test]
+    fn capped_certificate_in_the_renewal_window_isnt_rechecked_immediately() {
+        let now = chrono::Utc::now();
+        let mut user = user(&[]);
+        user.spec.valid_until = Some(now + chrono::Duration::hours(1));
+        // The last 20% of the lifetime started an hour ago.
+        let validity = CertValidity {
+            not_before: now - chrono::Duration::hours(9),
+            not_after: now + chrono::Duration::hours(1),
+        };
+        assert!(validity.needs_renewal(20));
+        assert_eq!(
+            until_next_check(&user, &validity, 20, now),
+            Duration::from_secs(60 * 10)
+        );
+    }
+
+    #[test]
+    fn overdue_renewal_is_rechecked_after_a_minute() {
+        let now = chrono::Utc::now();
+        let validity = CertValidity {
+            not_before: now - chrono::Duration::hours(9),
+            not_after: now + chrono::Duration::hours(1),
+        };
+        assert_eq!(
+            until_next_check(&user(&[]), &validity, 20, now),
+            Duration::from_secs(60)
+        );
+    }
+
+    #[test]
+    fn certificate_of_a_replaced_request_doesnt_fit() {
+        let user = user(&[]);
+        let (_, old_csr) = request(&user);
+        let (_, csr) = request(&user);
+        let old_cert = sign(&old_csr);
+        assert!(cert_fits(&old_cert, &old_csr, &user, false).unwrap());
+        assert!(!cert_fits(&old_cert, &csr, &user, false).unwrap());
+    }
+
+    #[test]
+    fn generations_dont_repeat() {
+        let now = chrono::Utc::now();
+        // Counter-based generations of older versions are superseded.
+        assert_eq!(next_generation(Some(3), now), now.timestamp());
+        // A recreated user doesn't get any of the generations used before.
+        assert_eq!(next_generation(None, now), now.timestamp());
+        // Keys generated within the same second or after the clock went back.
+        assert_eq!(
+            next_generation(Some(now.timestamp()), now),
+            now.timestamp() + 1
+        );
+    }
 }
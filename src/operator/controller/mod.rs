@@ -1,14 +1,26 @@
 use std::{sync::Arc, time::Duration};
 
 use futures::StreamExt;
-use k8s_openapi::api::certificates::v1::CertificateSigningRequest;
-use kube::{runtime::controller::Action, Api};
+use k8s_openapi::api::{certificates::v1::CertificateSigningRequest, core::v1::Namespace};
+use kube::{
+    runtime::{
+        controller::Action,
+        reflector::{ObjectRef, Store},
+    },
+    Api, ResourceExt,
+};
 
-use crate::{crds::managed_user::ManagedUser, operator::error::KuoError};
+use crate::{
+    args::SignerBackend,
+    crds::{access_request::AccessRequest, managed_group::ManagedGroup, managed_user::ManagedUser},
+    operator::{error::KuoError, issuer::cert_manager::CertificateRequest},
+};
 
-use super::{ctx::OperatorCtx, error::KuoResult};
+use super::{cache, ctx::OperatorCtx, error::KuoResult};
 
+mod access_request;
 pub mod csr;
+mod managed_group;
 mod managed_user;
 
 #[inline]
@@ -26,23 +38,103 @@ where
     T::DynamicType: Eq + std::hash::Hash + Clone,
 {
     match error {
-        KuoError::CannotReconcile(_) => Action::requeue(Duration::from_secs(60 * 5)),
+        KuoError::CannotReconcile(_) | KuoError::FieldConflict(_) => {
+            Action::requeue(Duration::from_secs(60 * 5))
+        }
         _ => Action::requeue(Duration::from_secs(60)),
     }
 }
 
+/// Map a namespace event to the objects whose roles are affected by it.
+fn namespace_mapper<K>(
+    store: Store<K>,
+    affected: fn(&K, &Namespace) -> bool,
+) -> impl Fn(Arc<Namespace>) -> Vec<ObjectRef<K>>
+where
+    K: kube::Resource<DynamicType = ()> + Clone + 'static,
+{
+    move |namespace| {
+        store
+            .state()
+            .into_iter()
+            .filter(|obj| affected(obj, &namespace))
+            .map(|obj| ObjectRef::from_obj(obj.as_ref()))
+            .collect()
+    }
+}
+
+/// Map an RBAC object to the user it was created for.
+#[allow(clippy::needless_pass_by_value)]
+fn user_mapper<K: kube::Resource>(obj: Arc<K>) -> Option<ObjectRef<ManagedUser>> {
+    obj.labels()
+        .get("kuo.github.com/user")
+        .map(|user| ObjectRef::new(user))
+}
+
+#[allow(clippy::too_many_lines)]
 pub async fn run(ctx: Arc<OperatorCtx>) -> KuoResult<()> {
     tracing::info!("Running operator controller");
-    let managed_user_controller = kube::runtime::Controller::new(
+    ctx.cache.wait_until_ready().await?;
+    let mut managed_user_controller = kube::runtime::Controller::new(
         Api::<ManagedUser>::all(ctx.client.clone()),
         kube::runtime::watcher::Config::default(),
     )
-    .run(
-        managed_user::reconcile,
-        default_on_error::<ManagedUser>,
-        ctx.clone(),
-    )
-    .for_each(|_| futures::future::ready(()));
+    // Signed CSRs are picked up by the managed user controller.
+    .owns(
+        Api::<CertificateSigningRequest>::all(ctx.client.clone()),
+        kube::runtime::watcher::Config {
+            label_selector: Some(String::from("app.kubernetes.io/managed-by=kuo-operator")),
+            ..Default::default()
+        },
+    );
+    // cert-manager CRDs might not be installed,
+    // so we watch them only if they are used.
+    if ctx.args.signer_backend == SignerBackend::CertManager {
+        // `owns` would look the owner up in the request's namespace,
+        // but managed users are cluster-scoped.
+        managed_user_controller = managed_user_controller.watches(
+            Api::<CertificateRequest>::namespaced(
+                ctx.client.clone(),
+                ctx.client.default_namespace(),
+            ),
+            kube::runtime::watcher::Config {
+                label_selector: Some(String::from("app.kubernetes.io/managed-by=kuo-operator")),
+                ..Default::default()
+            },
+            |request| {
+                request
+                    .owner_references()
+                    .iter()
+                    .filter(|owner| owner.kind == "ManagedUser")
+                    .map(|owner| ObjectRef::<ManagedUser>::new(&owner.name))
+                    .collect::<Vec<_>>()
+            },
+        );
+    }
+    // Manual changes of the user's roles and bindings are reverted right away.
+    // Changes come from the cache, so the objects aren't watched twice.
+    let changes = &ctx.cache.changes;
+    managed_user_controller = managed_user_controller
+        .watches_shared_stream(cache::subscribe(&changes.roles), user_mapper)
+        .watches_shared_stream(cache::subscribe(&changes.role_bindings), user_mapper)
+        .watches_shared_stream(cache::subscribe(&changes.cluster_roles), user_mapper)
+        .watches_shared_stream(
+            cache::subscribe(&changes.cluster_role_bindings),
+            user_mapper,
+        );
+    // Namespaces matching the permissions may be created, relabelled or deleted at any time.
+    let users = managed_user_controller.store();
+    let managed_user_controller = managed_user_controller
+        .watches_shared_stream(
+            cache::subscribe(&ctx.cache.changes.namespaces),
+            namespace_mapper(users, ManagedUser::affected_by_namespace),
+        )
+        .run(
+            managed_user::reconcile,
+            default_on_error::<ManagedUser>,
+            ctx.clone(),
+        )
+        .for_each(|_| futures::future::ready(()));
     let csr_controller = kube::runtime::Controller::new(
         Api::<CertificateSigningRequest>::all(ctx.client.clone()),
         kube::runtime::watcher::Config {
@@ -50,12 +142,80 @@ pub async fn run(ctx: Arc<OperatorCtx>) -> KuoResult<()> {
             ..Default::default()
         },
     )
+    // Requests are approved only for the current identity of their owner.
+    .watches_shared_stream(cache::subscribe(&ctx.cache.changes.users), {
+        let csrs = ctx.cache.csrs.clone();
+        move |user| {
+            csrs.state()
+                .into_iter()
+                .filter(|csr| {
+                    csr.owner_references()
+                        .iter()
+                        .any(|owner| owner.kind == "ManagedUser" && owner.name == user.name_any())
+                })
+                .map(|csr| ObjectRef::from_obj(csr.as_ref()))
+                .collect::<Vec<_>>()
+        }
+    })
     .run(
         csr::reconcile,
         default_on_error::<CertificateSigningRequest>,
         ctx.clone(),
     )
     .for_each(|_| futures::future::ready(()));
+    let access_request_controller = kube::runtime::Controller::new(
+        Api::<AccessRequest>::all(ctx.client.clone()),
+        kube::runtime::watcher::Config::default(),
+    );
+    let requests = access_request_controller.store();
+    let user_requests = requests.clone();
+    let access_request_controller = access_request_controller
+        // Bindings follow the identity of the user, which changes on credential rotation.
+        .watches_shared_stream(cache::subscribe(&ctx.cache.changes.users), move |user| {
+            user_requests
+                .state()
+                .into_iter()
+                .filter(|request| request.spec.user == user.name_any())
+                .map(|request| ObjectRef::from_obj(request.as_ref()))
+                .collect::<Vec<_>>()
+        })
+        .watches_shared_stream(
+            cache::subscribe(&ctx.cache.changes.namespaces),
+            namespace_mapper(requests, AccessRequest::affected_by_namespace),
+        )
+        .run(
+            access_request::reconcile,
+            default_on_error::<AccessRequest>,
+            ctx.clone(),
+        )
+        .for_each(|_| futures::future::ready(()));
+    let managed_group_controller = kube::runtime::Controller::new(
+        Api::<ManagedGroup>::all(ctx.client.clone()),
+        kube::runtime::watcher::Config::default(),
+    );
+    // Groups are reconciled when their members change.
+    let groups = managed_group_controller.store();
+    let namespace_groups = groups.clone();
+    let managed_group_controller = managed_group_controller
+        .watches_shared_stream(cache::subscribe(&ctx.cache.changes.users), move |user| {
+            groups
+                .state()
+                .into_iter()
+                // Former members have to be unbound as well.
+                .filter(|group| group.selects(&user) || group.has_member(&user))
+                .map(|group| ObjectRef::from_obj(group.as_ref()))
+                .collect::<Vec<_>>()
+        })
+        .watches_shared_stream(
+            cache::subscribe(&ctx.cache.changes.namespaces),
+            namespace_mapper(namespace_groups, ManagedGroup::affected_by_namespace),
+        )
+        .run(
+            managed_group::reconcile,
+            default_on_error::<ManagedGroup>,
+            ctx.clone(),
+        )
+        .for_each(|_| futures::future::ready(()));
 
     tokio::select! {
         () = managed_user_controller => {
@@ -64,6 +224,12 @@ pub async fn run(ctx: Arc<OperatorCtx>) -> KuoResult<()> {
         () = csr_controller => {
             tracing::warn!("CSR controller stopped. Exiting.");
         }
+        () = access_request_controller => {
+            tracing::warn!("Access request controller stopped. Exiting.");
+        }
+        () = managed_group_controller => {
+            tracing::warn!("Managed group controller stopped. Exiting.");
+        }
     }
     Ok(())
 }
@@ -1,17 +1,26 @@
-use std::time::Duration;
+use std::{sync::Arc, time::Duration};
 
 use clap::Parser;
+use kube::runtime::events::Reporter;
 use lettre::transport::smtp::authentication::Credentials;
 
 use crate::args::OperatorArgs;
 
-use super::error::KuoResult;
+use super::{
+    cache::OperatorCache,
+    error::KuoResult,
+    issuer::{self, CertificateIssuer},
+    migrations,
+};
 
 #[derive(Clone)]
 pub struct OperatorCtx {
     pub client: kube::Client,
     pub args: OperatorArgs,
     pub smtp: Option<lettre::AsyncSmtpTransport<lettre::Tokio1Executor>>,
+    pub issuer: Arc<dyn CertificateIssuer>,
+    pub reporter: Reporter,
+    pub cache: OperatorCache,
 }
 
 impl OperatorCtx {
@@ -49,6 +58,20 @@ impl OperatorCtx {
         let client = kube::Client::try_default().await?;
         tracing::info!("Connected to Kubernetes");
         let smtp = Self::get_smtp_transport(&args).await?;
-        Ok(Self { client, args, smtp })
+        migrations::run(&client).await;
+        let cache = OperatorCache::new(&client);
+        let issuer = issuer::from_args(client.clone(), &args, &cache);
+        let reporter = Reporter {
+            controller: String::from("kuo-operator"),
+            instance: std::env::var("HOSTNAME").ok(),
+        };
+        Ok(Self {
+            client,
+            args,
+            smtp,
+            issuer,
+            reporter,
+            cache,
+        })
     }
 }
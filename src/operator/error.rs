@@ -6,16 +6,24 @@ pub enum KuoError {
     CannotReconcile(String),
     #[error("CSR was denied")]
     CSRDenied,
+    #[error("Invalid certificate. Reason: {0}")]
+    InvalidCertificate(String),
+    #[error("Invalid certificate request. Reason: {0}")]
+    InvalidCertificateRequest(String),
     #[error("Cannot parse user secret data")]
     InvalidUserSecretData,
     #[error("Cannot get root kube certificate. Reason: {0}")]
     CannotGetRootCert(String),
+    #[error("Cannot load certificate authority. Reason: {0}")]
+    CannotLoadCa(String),
     #[error("Cannot generate kubeconfig. Reason: {0}")]
     CannotGenerateKubeconfig(String),
     #[error("StdError: {0}")]
     StdError(#[from] std::io::Error),
     #[error("OpensslError: {0}")]
     OpensslError(#[from] openssl::error::ErrorStack),
+    #[error("Fields are managed by another controller: {0}")]
+    FieldConflict(String),
     #[error("KubeError: {0}")]
     KubeError(#[from] kube::Error),
     #[error("Utf8Error: {0}")]
@@ -28,4 +36,18 @@ pub enum KuoError {
     EmailError(#[from] lettre::error::Error),
     #[error("Cannot serialize/deserialize YAML. Reason: {0}")]
     YAMLError(#[from] serde_yaml::Error),
+    #[error("Cannot serialize/deserialize JSON. Reason: {0}")]
+    JSONError(#[from] serde_json::Error),
+    #[error("Cannot decode base64. Reason: {0}")]
+    Base64Error(#[from] base64::DecodeError),
+    #[error("Blocking task failed. Reason: {0}")]
+    JoinError(#[from] tokio::task::JoinError),
+}
+
+impl KuoError {
+    /// Check whether the error is caused by a missing Kubernetes object.
+    #[must_use]
+    pub const fn is_not_found(&self) -> bool {
+        matches!(self, Self::KubeError(kube::Error::Api(err)) if err.code == 404)
+    }
 }
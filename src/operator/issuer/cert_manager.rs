@@ -0,0 +1,217 @@
+use base64::{engine::general_purpose::STANDARD as BASE64_STANDARD, Engine};
+use kube::{
+    api::{DeleteParams, ObjectMeta, Patch, PatchParams, PostParams},
+    CustomResource, ResourceExt,
+};
+use openssl::x509::X509Req;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    args::OperatorArgs,
+    crds::managed_user::ManagedUser,
+    operator::{error::KuoResult, utils::meta::ObjectMetaKuoExt},
+};
+
+use super::{CertificateIssuer, IssuanceState};
+
+/// Subset of the cert-manager `CertificateRequest` spec used by the operator.
+#[derive(CustomResource, Debug, Serialize, Deserialize, Default, Clone, JsonSchema)]
+#[kube(
+    group = "cert-manager.io",
+    version = "v1",
+    kind = "CertificateRequest",
+    namespaced,
+    status = "CertificateRequestStatus"
+)]
+#[serde(rename_all = "camelCase")]
+pub struct CertificateRequestSpec {
+    /// Base64-encoded PEM certificate request.
+    pub request: String,
+    pub issuer_ref: IssuerRef,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duration: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub usages: Option<Vec<String>>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Default, Clone, JsonSchema)]
+pub struct IssuerRef {
+    pub name: String,
+    pub kind: String,
+    pub group: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Default, Clone, JsonSchema)]
+pub struct CertificateRequestStatus {
+    #[serde(default)]
+    pub conditions: Vec<CertificateRequestCondition>,
+    /// Base64-encoded PEM certificate.
+    #[serde(default)]
+    pub certificate: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Default, Clone, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct CertificateRequestCondition {
+    #[serde(rename = "type")]
+    pub type_: String,
+    pub status: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_transition_time: Option<String>,
+}
+
+/// Issuer which creates cert-manager `CertificateRequest` objects.
+///
+/// Requests are created in the operator's namespace
+/// and reference the configured `Issuer` or `ClusterIssuer`.
+/// The operator approves its own requests.
+pub struct CertManagerIssuer {
+    client: kube::Client,
+    issuer_ref: IssuerRef,
+    cert_expiration_seconds: i32,
+}
+
+impl CertManagerIssuer {
+    #[must_use]
+    pub fn new(client: kube::Client, args: &OperatorArgs) -> Self {
+        Self {
+            client,
+            issuer_ref: IssuerRef {
+                name: args.cert_manager_issuer_name.clone(),
+                kind: args.cert_manager_issuer_kind.clone(),
+                group: args.cert_manager_issuer_group.clone(),
+            },
+            cert_expiration_seconds: args.cert_expiration_seconds,
+        }
+    }
+
+    /// Name of the user's `CertificateRequest`.
+    #[must_use]
+    pub fn request_name(user: &ManagedUser) -> String {
+        format!("kuo-{}", user.name_any())
+    }
+
+    fn api(&self) -> kube::Api<CertificateRequest> {
+        kube::Api::namespaced(self.client.clone(), self.client.default_namespace())
+    }
+
+    async fn approve(&self, request: &CertificateRequest) -> KuoResult<()> {
+        tracing::info!("Approving CertificateRequest");
+        let mut conditions = request
+            .status
+            .as_ref()
+            .map(|status| status.conditions.clone())
+            .unwrap_or_default();
+        conditions.push(CertificateRequestCondition {
+            type_: String::from("Approved"),
+            status: String::from("True"),
+            reason: Some(String::from("KuoApproved")),
+            message: Some(String::from("Certificate request approved by kuo")),
+            last_transition_time: Some(chrono::Utc::now().to_rfc3339()),
+        });
+        self.api()
+            .patch_status(
+                &request.name_any(),
+                &PatchParams::default(),
+                &Patch::Merge(serde_json::json!({
+                    "status": { "conditions": conditions }
+                })),
+            )
+            .await?;
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait]
+impl CertificateIssuer for CertManagerIssuer {
+    async fn submit(&self, user: &ManagedUser, request: &X509Req) -> KuoResult<()> {
+        let mut meta = ObjectMeta::default();
+        meta.insert_label("app.kubernetes.io/managed-by", "kuo-operator");
+        meta.name = Some(Self::request_name(user));
+        meta.add_owner(user);
+        let lifetime = user.cert_expiration_seconds(self.cert_expiration_seconds);
+        self.api()
+            .create(
+                &PostParams::default(),
+                &CertificateRequest {
+                    metadata: meta,
+                    spec: CertificateRequestSpec {
+                        request: BASE64_STANDARD.encode(request.to_pem()?),
+                        issuer_ref: self.issuer_ref.clone(),
+                        duration: Some(format!("{lifetime}s")),
+                        usages: Some(vec![
+                            String::from("digital signature"),
+                            String::from("key encipherment"),
+                            String::from("client auth"),
+                        ]),
+                    },
+                    status: None,
+                },
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn poll(&self, user: &ManagedUser) -> KuoResult<IssuanceState> {
+        let Some(request) = self.api().get_opt(&Self::request_name(user)).await? else {
+            return Ok(IssuanceState::NotFound);
+        };
+        let status = request.status.clone().unwrap_or_default();
+        let has_condition = |type_: &str| {
+            status
+                .conditions
+                .iter()
+                .any(|c| c.type_ == type_ && c.status == "True")
+        };
+        // cert-manager marks denied and failed requests
+        // with the `Ready` condition set to `False`.
+        let failure = status.conditions.iter().find(|c| {
+            ((c.type_ == "Denied" || c.type_ == "InvalidRequest") && c.status == "True")
+                || (c.type_ == "Ready"
+                    && c.status == "False"
+                    && matches!(c.reason.as_deref(), Some("Denied" | "Failed")))
+        });
+        if let Some(c) = failure {
+            let condition = if c.type_ == "Ready" {
+                c.reason.clone().unwrap_or_default()
+            } else if c.type_ == "Denied" {
+                String::from("Denied")
+            } else {
+                String::from("Failed")
+            };
+            return Ok(IssuanceState::Failed {
+                condition,
+                reason: c.reason.clone().unwrap_or_else(|| c.type_.clone()),
+                message: c.message.clone().unwrap_or_default(),
+            });
+        }
+        let pending = || -> KuoResult<IssuanceState> {
+            Ok(IssuanceState::Pending(String::from_utf8(
+                BASE64_STANDARD.decode(&request.spec.request)?,
+            )?))
+        };
+        if !has_condition("Approved") && !has_condition("Denied") {
+            self.approve(&request).await?;
+            return pending();
+        }
+        match status.certificate {
+            Some(cert) if has_condition("Ready") => Ok(IssuanceState::Issued(String::from_utf8(
+                BASE64_STANDARD.decode(cert)?,
+            )?)),
+            _ => pending(),
+        }
+    }
+
+    async fn revoke(&self, user: &ManagedUser) -> KuoResult<()> {
+        let name = Self::request_name(user);
+        if self.api().get_metadata_opt(&name).await?.is_some() {
+            self.api().delete(&name, &DeleteParams::default()).await?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,137 @@
+use std::sync::Arc;
+
+use k8s_openapi::{
+    api::certificates::v1::{CertificateSigningRequest, CertificateSigningRequestSpec},
+    ByteString,
+};
+use kube::{
+    api::{ObjectMeta, PostParams},
+    runtime::reflector::{ObjectRef, Store},
+    ResourceExt,
+};
+use openssl::x509::X509Req;
+
+use crate::{
+    args::OperatorArgs,
+    crds::managed_user::ManagedUser,
+    operator::{
+        cache::{MANAGED_BY, MANAGED_BY_LABEL},
+        error::KuoResult,
+        utils::{meta::ObjectMetaKuoExt, rbac},
+    },
+};
+
+use super::{CertificateIssuer, IssuanceState};
+
+/// Issuer which uses kubernetes certificates API.
+///
+/// Requests are approved by the CSR controller
+/// and signed by the signer with the configured name.
+pub struct KubeCsrIssuer {
+    client: kube::Client,
+    csrs: Store<CertificateSigningRequest>,
+    signer_name: String,
+    cert_expiration_seconds: i32,
+}
+
+impl KubeCsrIssuer {
+    #[must_use]
+    pub fn new(
+        client: kube::Client,
+        args: &OperatorArgs,
+        csrs: Store<CertificateSigningRequest>,
+    ) -> Self {
+        Self {
+            client,
+            csrs,
+            signer_name: args.signer_name.clone(),
+            cert_expiration_seconds: args.cert_expiration_seconds,
+        }
+    }
+
+    /// Name of the user's `CertificateSigningRequest`.
+    #[must_use]
+    pub fn csr_name(user: &ManagedUser) -> String {
+        format!("kuo-{}", user.name_any())
+    }
+
+    /// Get the `Denied` or `Failed` condition of the CSR as a failed state.
+    #[must_use]
+    pub fn failure(csr: &CertificateSigningRequest) -> Option<IssuanceState> {
+        let conditions = csr.status.as_ref()?.conditions.as_ref()?;
+        conditions
+            .iter()
+            .find(|c| (c.type_ == "Denied" || c.type_ == "Failed") && c.status == "True")
+            .map(|c| IssuanceState::Failed {
+                condition: c.type_.clone(),
+                reason: c.reason.clone().unwrap_or_else(|| c.type_.clone()),
+                message: c.message.clone().unwrap_or_default(),
+            })
+    }
+
+    fn api(&self) -> kube::Api<CertificateSigningRequest> {
+        kube::Api::all(self.client.clone())
+    }
+}
+
+#[async_trait::async_trait]
+impl CertificateIssuer for KubeCsrIssuer {
+    async fn submit(&self, user: &ManagedUser, request: &X509Req) -> KuoResult<()> {
+        let mut meta = ObjectMeta::default();
+        meta.insert_label(MANAGED_BY_LABEL, MANAGED_BY);
+        meta.name = Some(Self::csr_name(user));
+        meta.add_owner(user);
+        self.api()
+            .create(
+                &PostParams::default(),
+                &CertificateSigningRequest {
+                    metadata: meta,
+                    spec: CertificateSigningRequestSpec {
+                        request: ByteString(request.to_pem()?),
+                        signer_name: self.signer_name.clone(),
+                        expiration_seconds: Some(
+                            user.cert_expiration_seconds(self.cert_expiration_seconds),
+                        ),
+                        usages: Some(vec![
+                            String::from("digital signature"),
+                            String::from("key encipherment"),
+                            String::from("client auth"),
+                        ]),
+                        ..Default::default()
+                    },
+                    status: None,
+                },
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn poll(&self, user: &ManagedUser) -> KuoResult<IssuanceState> {
+        let name = Self::csr_name(user);
+        // Freshly submitted requests might not be cached yet.
+        let cached = self
+            .csrs
+            .get(&ObjectRef::new(&name))
+            .map(Arc::unwrap_or_clone);
+        let csr = match cached {
+            Some(csr) => csr,
+            None => match self.api().get_opt(&name).await? {
+                Some(csr) => csr,
+                None => return Ok(IssuanceState::NotFound),
+            },
+        };
+        if let Some(failed) = Self::failure(&csr) {
+            return Ok(failed);
+        }
+        let Some(cert) = csr.status.and_then(|status| status.certificate) else {
+            return Ok(IssuanceState::Pending(String::from_utf8(
+                csr.spec.request.0,
+            )?));
+        };
+        Ok(IssuanceState::Issued(String::from_utf8(cert.0)?))
+    }
+
+    async fn revoke(&self, user: &ManagedUser) -> KuoResult<()> {
+        rbac::delete_if_exists(self.api(), &Self::csr_name(user)).await
+    }
+}
@@ -0,0 +1,158 @@
+use std::{collections::HashMap, sync::Mutex};
+
+use k8s_openapi::api::core::v1::Secret;
+use kube::ResourceExt;
+use openssl::{
+    asn1::Asn1Time,
+    bn::{BigNum, MsbOption},
+    pkey::{Id, PKey, Private},
+    x509::{
+        extension::{AuthorityKeyIdentifier, BasicConstraints, ExtendedKeyUsage, KeyUsage},
+        X509Req, X509,
+    },
+};
+
+use crate::{
+    args::OperatorArgs,
+    crds::{key_algorithm::KeyAlgorithm, managed_user::ManagedUser},
+    operator::{
+        error::{KuoError, KuoResult},
+        utils::cert::asn1_to_chrono,
+    },
+};
+
+use super::{CertificateIssuer, IssuanceState};
+
+/// Certificate authority which signs user certificates
+/// without the kubernetes certificates API.
+///
+/// The kube API server must trust this authority
+/// (e.g. it should be passed in `--client-ca-file`).
+pub struct LocalCa {
+    cert: X509,
+    key: PKey<Private>,
+}
+
+impl LocalCa {
+    /// Create the authority from its certificate and private key.
+    #[must_use]
+    pub const fn new(cert: X509, key: PKey<Private>) -> Self {
+        Self { cert, key }
+    }
+
+    /// Load CA certificate and key from the secret.
+    ///
+    /// The secret should be of the `kubernetes.io/tls` type.
+    pub async fn load(client: kube::Client, name: &str) -> KuoResult<Self> {
+        let secret = kube::Api::<Secret>::namespaced(client.clone(), client.default_namespace())
+            .get_opt(name)
+            .await?
+            .ok_or_else(|| KuoError::CannotLoadCa(format!("The Secret {name} doesn't exist.")))?;
+        let data = secret.data.unwrap_or_default();
+        let (Some(cert), Some(key)) = (data.get("tls.crt"), data.get("tls.key")) else {
+            return Err(KuoError::CannotLoadCa(format!(
+                "The Secret {name} must contain tls.crt and tls.key."
+            )));
+        };
+        Ok(Self {
+            cert: X509::from_pem(&cert.0)?,
+            key: PKey::private_key_from_pem(&key.0)?,
+        })
+    }
+
+    /// Sign the request with the CA key.
+    ///
+    /// The resulting certificate can only be used for client authentication.
+    /// It never outlives the CA certificate.
+    pub fn sign(&self, req: &X509Req, lifetime_seconds: i32) -> KuoResult<X509> {
+        let pubkey = req.public_key()?;
+        if !req.verify(&pubkey)? {
+            return Err(KuoError::InvalidCertificateRequest(String::from(
+                "Signature of the request is invalid",
+            )));
+        }
+        let now = chrono::Utc::now().timestamp();
+        let ca_not_after = asn1_to_chrono(self.cert.not_after())?.timestamp();
+        let not_after = (now + i64::from(lifetime_seconds)).min(ca_not_after);
+        let mut serial = BigNum::new()?;
+        serial.rand(127, MsbOption::MAYBE_ZERO, false)?;
+
+        let mut builder = X509::builder()?;
+        builder.set_version(2)?;
+        builder.set_serial_number(serial.to_asn1_integer()?.as_ref())?;
+        builder.set_subject_name(req.subject_name())?;
+        builder.set_issuer_name(self.cert.subject_name())?;
+        builder.set_pubkey(&pubkey)?;
+        builder.set_not_before(Asn1Time::from_unix(now)?.as_ref())?;
+        builder.set_not_after(Asn1Time::from_unix(not_after)?.as_ref())?;
+        builder.append_extension(BasicConstraints::new().critical().build()?)?;
+        let mut key_usage = KeyUsage::new();
+        key_usage.critical().digital_signature();
+        if pubkey.id() == Id::RSA {
+            key_usage.key_encipherment();
+        }
+        builder.append_extension(key_usage.build()?)?;
+        builder.append_extension(ExtendedKeyUsage::new().client_auth().build()?)?;
+        let authority_key_id = AuthorityKeyIdentifier::new()
+            .keyid(false)
+            .build(&builder.x509v3_context(Some(&self.cert), None))?;
+        builder.append_extension(authority_key_id)?;
+        builder.sign(&self.key, KeyAlgorithm::signature_digest(&self.key))?;
+        Ok(builder.build())
+    }
+}
+
+/// Issuer which signs certificates with the [`LocalCa`].
+///
+/// Requests are signed right away, and issued certificates
+/// are kept in memory until they are stored in the user's secret.
+pub struct LocalCaIssuer {
+    client: kube::Client,
+    ca_secret_name: String,
+    cert_expiration_seconds: i32,
+    issued: Mutex<HashMap<String, String>>,
+}
+
+impl LocalCaIssuer {
+    #[must_use]
+    pub fn new(client: kube::Client, args: &OperatorArgs) -> Self {
+        Self {
+            client,
+            ca_secret_name: args.ca_secret_name.clone(),
+            cert_expiration_seconds: args.cert_expiration_seconds,
+            issued: Mutex::default(),
+        }
+    }
+
+    fn issued(&self) -> std::sync::MutexGuard<'_, HashMap<String, String>> {
+        super::lock(&self.issued)
+    }
+}
+
+#[async_trait::async_trait]
+impl CertificateIssuer for LocalCaIssuer {
+    async fn submit(&self, user: &ManagedUser, request: &X509Req) -> KuoResult<()> {
+        tracing::info!("Signing certificate with the local CA");
+        let lifetime = user.cert_expiration_seconds(self.cert_expiration_seconds);
+        let cert = LocalCa::load(self.client.clone(), &self.ca_secret_name)
+            .await?
+            .sign(request, lifetime)?;
+        let cert = String::from_utf8(cert.to_pem()?)?;
+        self.issued().insert(user.name_any(), cert);
+        Ok(())
+    }
+
+    async fn poll(&self, user: &ManagedUser) -> KuoResult<IssuanceState> {
+        Ok(self
+            .issued()
+            .get(&user.name_any())
+            .map_or(IssuanceState::NotFound, |cert| {
+                IssuanceState::Issued(cert.clone())
+            }))
+    }
+
+    async fn revoke(&self, user: &ManagedUser) -> KuoResult<()> {
+        self.issued().remove(&user.name_any());
+        Ok(())
+    }
+}
@@ -0,0 +1,50 @@
+use std::{collections::HashMap, sync::Mutex};
+
+use kube::ResourceExt;
+use openssl::x509::X509Req;
+
+use crate::{crds::managed_user::ManagedUser, operator::error::KuoResult};
+
+use super::{CertificateIssuer, IssuanceState};
+
+/// Issuer which keeps requests in memory and never signs them by itself.
+///
+/// It's only built for tests of reconcilers without a cluster:
+/// the state of every request is driven with [`MemoryIssuer::set_state`].
+#[derive(Default)]
+pub struct MemoryIssuer {
+    requests: Mutex<HashMap<String, IssuanceState>>,
+}
+
+impl MemoryIssuer {
+    fn requests(&self) -> std::sync::MutexGuard<'_, HashMap<String, IssuanceState>> {
+        super::lock(&self.requests)
+    }
+
+    /// Set the state of the user's request, e.g. sign or deny it.
+    pub fn set_state(&self, username: &str, state: IssuanceState) {
+        self.requests().insert(String::from(username), state);
+    }
+}
+
+#[async_trait::async_trait]
+impl CertificateIssuer for MemoryIssuer {
+    async fn submit(&self, user: &ManagedUser, request: &X509Req) -> KuoResult<()> {
+        let request = String::from_utf8(request.to_pem()?)?;
+        self.set_state(&user.name_any(), IssuanceState::Pending(request));
+        Ok(())
+    }
+
+    async fn poll(&self, user: &ManagedUser) -> KuoResult<IssuanceState> {
+        Ok(self
+            .requests()
+            .get(&user.name_any())
+            .cloned()
+            .unwrap_or(IssuanceState::NotFound))
+    }
+
+    async fn revoke(&self, user: &ManagedUser) -> KuoResult<()> {
+        self.requests().remove(&user.name_any());
+        Ok(())
+    }
+}
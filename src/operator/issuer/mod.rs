@@ -0,0 +1,81 @@
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+use openssl::x509::X509Req;
+
+use crate::{
+    args::{OperatorArgs, SignerBackend},
+    crds::managed_user::ManagedUser,
+};
+
+use super::{cache::OperatorCache, error::KuoResult};
+
+pub mod cert_manager;
+pub mod kube_csr;
+pub mod local_ca;
+#[cfg(test)]
+pub mod memory;
+
+/// State of the user's certificate request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IssuanceState {
+    /// There's no request for the user.
+    NotFound,
+    /// Request is waiting for a signature. Contains PEM-encoded request.
+    Pending(String),
+    /// Request has been signed. Contains PEM-encoded certificate.
+    Issued(String),
+    /// Request has been denied or has failed.
+    /// The issuer won't sign it, so it has to be re-requested.
+    Failed {
+        /// Either `Denied` or `Failed`.
+        condition: String,
+        /// Reason reported by the issuer.
+        reason: String,
+        /// Message reported by the issuer.
+        message: String,
+    },
+}
+
+/// Backend which issues certificates for users.
+///
+/// Every user has at most one outstanding request at a time.
+#[async_trait::async_trait]
+pub trait CertificateIssuer: Send + Sync {
+    /// Submit a new certificate request for the user.
+    async fn submit(&self, user: &ManagedUser, request: &X509Req) -> KuoResult<()>;
+
+    /// Get the state of the user's outstanding request.
+    async fn poll(&self, user: &ManagedUser) -> KuoResult<IssuanceState>;
+
+    /// Revoke the user's outstanding request.
+    ///
+    /// It's called once the issued certificate is stored,
+    /// or when the request is no longer needed.
+    async fn revoke(&self, user: &ManagedUser) -> KuoResult<()>;
+}
+
+/// Create the issuer selected in the operator's arguments.
+#[must_use]
+pub fn from_args(
+    client: kube::Client,
+    args: &OperatorArgs,
+    cache: &OperatorCache,
+) -> Arc<dyn CertificateIssuer> {
+    match args.signer_backend {
+        SignerBackend::Kubernetes => Arc::new(kube_csr::KubeCsrIssuer::new(
+            client,
+            args,
+            cache.csrs.clone(),
+        )),
+        SignerBackend::LocalCa => Arc::new(local_ca::LocalCaIssuer::new(client, args)),
+        SignerBackend::CertManager => Arc::new(cert_manager::CertManagerIssuer::new(client, args)),
+    }
+}
+
+/// Lock the requests kept in memory by an issuer.
+///
+/// Issuers always leave their maps in a consistent state,
+/// so it's safe to use them even if the lock was poisoned.
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
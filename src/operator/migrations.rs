@@ -0,0 +1,201 @@
+use std::collections::BTreeMap;
+
+use k8s_openapi::{
+    api::rbac::v1::{ClusterRole, ClusterRoleBinding, PolicyRule, Role, RoleBinding},
+    NamespaceResourceScope,
+};
+use kube::{
+    api::{ListParams, Patch, PatchParams},
+    ResourceExt,
+};
+use serde::de::DeserializeOwned;
+
+use crate::crds::{
+    access_request::ACCESS_REQUEST_LABEL, inline_permissions::Permission,
+    managed_group::GROUP_LABEL, managed_user::ManagedUser,
+};
+
+use super::{
+    cache::{MANAGED_BY, MANAGED_BY_LABEL},
+    error::KuoResult,
+    utils::{
+        digest::digest,
+        rbac::{self, DIGEST_LABEL},
+    },
+};
+
+/// Check whether the role has exactly the rules of the permissions.
+fn has_rules(rules: Option<&[PolicyRule]>, permissions: &[Permission]) -> bool {
+    let rules = rules.unwrap_or_default();
+    rules.len() == permissions.len()
+        && rules
+            .iter()
+            .zip(permissions)
+            .all(|(rule, permission)| *rule == PolicyRule::from(permission.clone()))
+}
+
+/// Label the legacy role with the digest of its permissions, so it's reused.
+/// Roles which don't match any permissions are deleted.
+async fn adopt_or_delete<K>(
+    api: kube::Api<K>,
+    name: &str,
+    permissions: Option<&[Permission]>,
+) -> KuoResult<()>
+where
+    K: kube::Resource + Clone + DeserializeOwned + std::fmt::Debug,
+{
+    let Some(permissions) = permissions else {
+        tracing::info!("Deleting stale legacy role {name}");
+        return rbac::delete_if_exists(api, name).await;
+    };
+    tracing::info!("Adopting legacy role {name}");
+    api.patch_metadata(
+        name,
+        &PatchParams::default(),
+        &Patch::Merge(serde_json::json!({
+            "metadata": {
+                "labels": { DIGEST_LABEL: digest(permissions)?, MANAGED_BY_LABEL: MANAGED_BY }
+            }
+        })),
+    )
+    .await?;
+    Ok(())
+}
+
+/// Adopt roles created before role names became stable.
+///
+/// Older releases named roles of inline permissions with `DefaultHasher`,
+/// which isn't stable across Rust releases, and didn't label them with digests.
+/// Roles of a user without the digest label are matched against the user's current
+/// permissions. Matching roles are labelled with the digest of their rules,
+/// which makes the operator reuse them instead of creating new ones.
+/// Roles that don't match any permissions are stale and deleted.
+async fn adopt_legacy_roles(client: &kube::Client) -> KuoResult<()> {
+    let users = kube::Api::<ManagedUser>::all(client.clone())
+        .list(&ListParams::default())
+        .await?
+        .into_iter()
+        .map(|user| (user.name_any(), user))
+        .collect::<BTreeMap<_, _>>();
+    let user_of = |labels: &BTreeMap<String, String>| {
+        labels
+            .get("kuo.github.com/user")
+            .and_then(|name| users.get(name))
+    };
+    let legacy = ListParams::default().labels(&format!("kuo.github.com/user,!{DIGEST_LABEL}"));
+    for role in kube::Api::<Role>::all(client.clone()).list(&legacy).await? {
+        let Some(user) = user_of(role.labels()) else {
+            continue;
+        };
+        // SAFETY: Roles are always namespaced.
+        let namespace = role.namespace().unwrap();
+        // Entries matching namespaces by selectors or patterns never had legacy names.
+        let permissions = user
+            .spec
+            .inline_permissions
+            .iter()
+            .flat_map(|permissions| permissions.namespaced_permissions.iter().flatten())
+            .filter(|namespaced| !namespaced.is_dynamic() && namespaced.namespace == namespace)
+            .map(|namespaced| namespaced.permissions.as_slice())
+            .find(|permissions| has_rules(role.rules.as_deref(), permissions));
+        adopt_or_delete(
+            kube::Api::<Role>::namespaced(client.clone(), &namespace),
+            &role.name_any(),
+            permissions,
+        )
+        .await?;
+    }
+    for role in kube::Api::<ClusterRole>::all(client.clone())
+        .list(&legacy)
+        .await?
+    {
+        let Some(user) = user_of(role.labels()) else {
+            continue;
+        };
+        let permissions = user
+            .spec
+            .inline_permissions
+            .as_ref()
+            .and_then(|permissions| permissions.cluster_permissions.as_deref())
+            .filter(|permissions| has_rules(role.rules.as_deref(), permissions));
+        adopt_or_delete(
+            kube::Api::<ClusterRole>::all(client.clone()),
+            &role.name_any(),
+            permissions,
+        )
+        .await?;
+    }
+    Ok(())
+}
+
+/// Label objects created by the operator with the managed-by label.
+///
+/// Older releases didn't set the label, so such objects would be invisible to the cache.
+async fn label_managed<K>(
+    api: kube::Api<K>,
+    namespaced_api: impl Fn(&str) -> kube::Api<K>,
+) -> KuoResult<()>
+where
+    K: kube::Resource + Clone + DeserializeOwned + std::fmt::Debug,
+{
+    for key in ["kuo.github.com/user", GROUP_LABEL, ACCESS_REQUEST_LABEL] {
+        let objects = api
+            .list_metadata(&ListParams {
+                label_selector: Some(format!("{key},{MANAGED_BY_LABEL}!={MANAGED_BY}")),
+                ..Default::default()
+            })
+            .await?;
+        for obj in objects {
+            obj.namespace()
+                .map_or_else(|| api.clone(), |ns| namespaced_api(&ns))
+                .patch_metadata(
+                    &obj.name_any(),
+                    &PatchParams::default(),
+                    &Patch::Merge(serde_json::json!({
+                        "metadata": { "labels": { MANAGED_BY_LABEL: MANAGED_BY } }
+                    })),
+                )
+                .await?;
+        }
+    }
+    Ok(())
+}
+
+/// Label namespaced objects created by the operator with the managed-by label.
+async fn label_managed_namespaced<K>(client: &kube::Client) -> KuoResult<()>
+where
+    K: kube::Resource<DynamicType = (), Scope = NamespaceResourceScope>
+        + Clone
+        + DeserializeOwned
+        + std::fmt::Debug,
+{
+    label_managed(kube::Api::<K>::all(client.clone()), |ns| {
+        kube::Api::namespaced(client.clone(), ns)
+    })
+    .await
+}
+
+/// Run all migrations.
+///
+/// Migrations must finish before the cache is filled,
+/// otherwise migrated objects might be missing from it during the first reconciliations.
+/// Failed migrations are logged and don't stop the operator.
+pub async fn run(client: &kube::Client) {
+    let res = async {
+        label_managed_namespaced::<Role>(client).await?;
+        label_managed_namespaced::<RoleBinding>(client).await?;
+        label_managed(kube::Api::<ClusterRole>::all(client.clone()), |_| {
+            kube::Api::all(client.clone())
+        })
+        .await?;
+        label_managed(kube::Api::<ClusterRoleBinding>::all(client.clone()), |_| {
+            kube::Api::all(client.clone())
+        })
+        .await?;
+        adopt_legacy_roles(client).await
+    }
+    .await;
+    if let Err(err) = res {
+        tracing::warn!("Cannot migrate objects. {err}");
+    }
+}
@@ -1,4 +1,7 @@
+pub mod cache;
 pub mod controller;
 pub mod ctx;
 pub mod error;
+pub mod issuer;
+pub mod migrations;
 pub mod utils;
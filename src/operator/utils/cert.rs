@@ -0,0 +1,86 @@
+use chrono::{DateTime, TimeDelta, Utc};
+use openssl::{
+    asn1::Asn1TimeRef,
+    nid::Nid,
+    pkey::{HasPublic, PKeyRef},
+    x509::{X509NameRef, X509},
+};
+
+use crate::operator::error::{KuoError, KuoResult};
+
+/// Validity period of an issued certificate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CertValidity {
+    pub not_before: DateTime<Utc>,
+    pub not_after: DateTime<Utc>,
+}
+
+/// Convert ASN.1 time to chrono.
+pub fn asn1_to_chrono(time: &Asn1TimeRef) -> KuoResult<DateTime<Utc>> {
+    let epoch = openssl::asn1::Asn1Time::from_unix(0)?;
+    let diff = epoch.diff(time)?;
+    let seconds = i64::from(diff.days) * 24 * 60 * 60 + i64::from(diff.secs);
+    DateTime::from_timestamp(seconds, 0)
+        .ok_or_else(|| KuoError::InvalidCertificate(format!("Timestamp {seconds} is out of range")))
+}
+
+impl CertValidity {
+    /// Read validity period from PEM-encoded certificate.
+    pub fn from_pem(pem: &str) -> KuoResult<Self> {
+        let cert = X509::from_pem(pem.as_bytes())?;
+        Ok(Self {
+            not_before: asn1_to_chrono(cert.not_before())?,
+            not_after: asn1_to_chrono(cert.not_after())?,
+        })
+    }
+
+    /// Total lifetime of the certificate.
+    #[must_use]
+    pub fn lifetime(&self) -> TimeDelta {
+        self.not_after - self.not_before
+    }
+
+    /// Moment after which the certificate should be renewed.
+    ///
+    /// The renewal window is the last `renew_before_percent`
+    /// percent of the certificate's lifetime.
+    #[must_use]
+    pub fn renew_at(&self, renew_before_percent: u8) -> DateTime<Utc> {
+        self.not_after - self.lifetime() * i32::from(renew_before_percent) / 100
+    }
+
+    #[must_use]
+    pub fn needs_renewal(&self, renew_before_percent: u8) -> bool {
+        Utc::now() >= self.renew_at(renew_before_percent)
+    }
+}
+
+/// Kubernetes groups encoded in the certificate.
+///
+/// Groups are stored as organization (`O`) entries of the subject.
+/// The result is sorted.
+pub fn cert_groups(pem: &str) -> KuoResult<Vec<String>> {
+    let cert = X509::from_pem(pem.as_bytes())?;
+    let mut groups = name_entries(cert.subject_name(), Nid::ORGANIZATIONNAME)?;
+    groups.sort();
+    Ok(groups)
+}
+
+/// Get the common names (`CN`) from the certificate's subject.
+pub fn cert_common_names(pem: &str) -> KuoResult<Vec<String>> {
+    let cert = X509::from_pem(pem.as_bytes())?;
+    name_entries(cert.subject_name(), Nid::COMMONNAME)
+}
+
+/// Values of all entries of the given type in the name.
+pub fn name_entries(name: &X509NameRef, nid: Nid) -> KuoResult<Vec<String>> {
+    name.entries_by_nid(nid)
+        .map(|entry| Ok(entry.data().as_utf8()?.to_string()))
+        .collect()
+}
+
+/// Check whether the certificate was issued for the given key.
+pub fn cert_matches_key<T: HasPublic>(pem: &str, key: &PKeyRef<T>) -> KuoResult<bool> {
+    let cert = X509::from_pem(pem.as_bytes())?;
+    Ok(cert.public_key()?.public_eq(key))
+}
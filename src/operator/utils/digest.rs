@@ -0,0 +1,47 @@
+use serde::Serialize;
+
+use crate::operator::error::KuoResult;
+
+/// Stable digest of the value, used in names of generated objects.
+///
+/// The value is serialized to JSON and hashed with SHA-256,
+/// the first 8 bytes of which are encoded as hex.
+pub fn digest<T: Serialize + ?Sized>(value: &T) -> KuoResult<String> {
+    let hash = openssl::sha::sha256(&serde_json::to_vec(value)?);
+    let mut prefix = [0; 8];
+    prefix.copy_from_slice(&hash[..8]);
+    Ok(format!("{:016x}", u64::from_be_bytes(prefix)))
+}
+
+#[cfg(test)]
+mod tests {
+    use k8s_openapi::api::rbac::v1::PolicyRule;
+
+    use super::digest;
+
+    /// Digests are part of the names and labels of existing objects,
+    /// so changing them would orphan everything created by older releases.
+    #[test]
+    fn digest_is_stable() {
+        let permissions = vec![
+            PolicyRule {
+                api_groups: Some(vec![String::new()]),
+                resources: Some(vec![String::from("pods"), String::from("pods/log")]),
+                verbs: vec![String::from("get"), String::from("list")],
+                ..PolicyRule::default()
+            },
+            PolicyRule {
+                api_groups: Some(vec![String::from("apps")]),
+                resources: Some(vec![String::from("deployments")]),
+                resource_names: Some(vec![String::from("api")]),
+                verbs: vec![String::from("patch")],
+                ..PolicyRule::default()
+            },
+        ];
+        assert_eq!(
+            serde_json::to_string(&permissions).unwrap(),
+            r#"[{"apiGroups":[""],"resources":["pods","pods/log"],"verbs":["get","list"]},{"apiGroups":["apps"],"resourceNames":["api"],"resources":["deployments"],"verbs":["patch"]}]"#
+        );
+        assert_eq!(digest(&permissions).unwrap(), "682cea09e856e6c9");
+    }
+}
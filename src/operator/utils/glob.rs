@@ -0,0 +1,70 @@
+/// Check whether the text matches a glob pattern.
+///
+/// `*` matches any sequence of characters and `?` matches a single character.
+#[must_use]
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern = pattern.chars().collect::<Vec<_>>();
+    let text = text.chars().collect::<Vec<_>>();
+    let (mut p, mut t) = (0, 0);
+    // Position of the last `*` in the pattern and the text position it was tried at.
+    let mut backtrack = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match backtrack {
+                // Let the last `*` consume one more character.
+                Some((star, start)) => {
+                    backtrack = Some((star, start + 1));
+                    p = star + 1;
+                    t = start + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::glob_match;
+
+    #[test]
+    fn glob_match_table() {
+        let cases = [
+            ("team-a-*", "team-a-dev", true),
+            ("team-a-*", "team-a-", true),
+            ("team-a-*", "team-b-dev", false),
+            ("*", "anything", true),
+            ("*", "", true),
+            ("**", "", true),
+            ("*-dev", "team-a-dev", true),
+            ("*-dev", "team-a-prod", false),
+            ("a*b*c", "aXXbYYc", true),
+            ("a*b*c", "aXXcYYb", false),
+            ("team-?", "team-a", true),
+            ("team-?", "team-", false),
+            ("team-?", "team-ab", false),
+            ("?", "", false),
+            ("", "", true),
+            ("", "default", false),
+            ("default", "", false),
+            ("default", "default", true),
+            ("dev-ф*", "dev-фыв", true),
+        ];
+        for (pattern, text, expected) in cases {
+            assert_eq!(
+                glob_match(pattern, text),
+                expected,
+                "glob_match({pattern:?}, {text:?})"
+            );
+        }
+    }
+}
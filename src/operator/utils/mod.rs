@@ -1,5 +1,9 @@
+pub mod cert;
+pub mod digest;
+pub mod glob;
 mod kube;
 pub mod meta;
+pub mod rbac;
 pub mod resource;
 
-pub use kube::get_kube_cert;
\ No newline at end of file
+pub use kube::get_kube_cert;
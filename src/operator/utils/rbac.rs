@@ -0,0 +1,278 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use k8s_openapi::{
+    api::{
+        core::v1::Namespace,
+        rbac::v1::{
+            ClusterRole, ClusterRoleBinding, PolicyRule, Role, RoleBinding, RoleRef, Subject,
+        },
+    },
+    Resource,
+};
+use kube::{
+    api::{DeleteParams, ObjectMeta},
+    ResourceExt,
+};
+use serde::de::DeserializeOwned;
+
+use crate::{
+    crds::inline_permissions::{AppliedPermissions, NamespacedPermissions, Permission},
+    operator::{
+        cache::{labelled, MANAGED_BY, MANAGED_BY_LABEL},
+        ctx::OperatorCtx,
+        error::KuoResult,
+        utils::{meta::ObjectMetaKuoExt, resource::KuoResourceExt},
+    },
+};
+
+/// Label with the digest of the rules of a generated role.
+pub const DIGEST_LABEL: &str = "kuo.github.com/permissions-digest";
+
+/// Delete the object unless it's already gone.
+///
+/// Objects from the cache might have been deleted in the meantime.
+pub async fn delete_if_exists<K>(api: kube::Api<K>, name: &str) -> KuoResult<()>
+where
+    K: kube::Resource + Clone + DeserializeOwned + std::fmt::Debug,
+{
+    match api.delete(name, &DeleteParams::default()).await {
+        Ok(_) => Ok(()),
+        Err(kube::Error::Api(err)) if err.code == 404 => Ok(()),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Delete all roles and role bindings which have the label with the given value.
+///
+/// Bindings are removed before the roles,
+/// so subjects lose access without waiting for the garbage collector.
+#[allow(clippy::missing_panics_doc)]
+pub async fn delete_labelled(ctx: &OperatorCtx, label: (&str, &str)) -> KuoResult<()> {
+    for binding in labelled(&ctx.cache.role_bindings, label) {
+        // SAFETY: Role bindings are always namespaced.
+        let namespace = binding.namespace().unwrap();
+        delete_if_exists(
+            kube::Api::<RoleBinding>::namespaced(ctx.client.clone(), &namespace),
+            &binding.name_any(),
+        )
+        .await?;
+    }
+    for binding in labelled(&ctx.cache.cluster_role_bindings, label) {
+        delete_if_exists(
+            kube::Api::<ClusterRoleBinding>::all(ctx.client.clone()),
+            &binding.name_any(),
+        )
+        .await?;
+    }
+    for role in labelled(&ctx.cache.roles, label) {
+        // SAFETY: Roles are always namespaced.
+        let namespace = role.namespace().unwrap();
+        delete_if_exists(
+            kube::Api::<Role>::namespaced(ctx.client.clone(), &namespace),
+            &role.name_any(),
+        )
+        .await?;
+    }
+    for role in labelled(&ctx.cache.cluster_roles, label) {
+        delete_if_exists(
+            kube::Api::<ClusterRole>::all(ctx.client.clone()),
+            &role.name_any(),
+        )
+        .await?;
+    }
+    Ok(())
+}
+
+/// Check whether a change of the namespace affects the permissions.
+///
+/// Namespaces which already have one of the `roles` in the `{namespace}/{name}` format
+/// are affected as well, so roles are removed once the namespace stops matching.
+#[must_use]
+pub fn namespace_affects(
+    namespace: &Namespace,
+    permissions: &[NamespacedPermissions],
+    roles: &[String],
+) -> bool {
+    let name = namespace.name_any();
+    permissions.iter().any(|p| p.matches(namespace))
+        || roles
+            .iter()
+            .any(|role| role.split_once('/').is_some_and(|(ns, _)| ns == name))
+}
+
+/// Remove objects with the label which are not named `name`,
+/// are in unknown namespaces, or are cluster-wide and not needed.
+#[allow(clippy::missing_panics_doc)]
+async fn remove_unneeded(
+    ctx: &OperatorCtx,
+    label: (&str, &str),
+    name: &str,
+    known_namespaces: &BTreeSet<String>,
+    cluster_needed: bool,
+) -> KuoResult<()> {
+    for binding in labelled(&ctx.cache.role_bindings, label) {
+        // SAFETY: Role bindings are always namespaced.
+        let namespace = binding.namespace().unwrap();
+        if binding.name_any() != *name || !known_namespaces.contains(&namespace) {
+            delete_if_exists(
+                kube::Api::<RoleBinding>::namespaced(ctx.client.clone(), &namespace),
+                &binding.name_any(),
+            )
+            .await?;
+        }
+    }
+    for role in labelled(&ctx.cache.roles, label) {
+        // SAFETY: Roles are always namespaced.
+        let namespace = role.namespace().unwrap();
+        if role.name_any() != *name || !known_namespaces.contains(&namespace) {
+            delete_if_exists(
+                kube::Api::<Role>::namespaced(ctx.client.clone(), &namespace),
+                &role.name_any(),
+            )
+            .await?;
+        }
+    }
+    for binding in labelled(&ctx.cache.cluster_role_bindings, label) {
+        if binding.name_any() != *name || !cluster_needed {
+            delete_if_exists(
+                kube::Api::<ClusterRoleBinding>::all(ctx.client.clone()),
+                &binding.name_any(),
+            )
+            .await?;
+        }
+    }
+    for role in labelled(&ctx.cache.cluster_roles, label) {
+        if role.name_any() != *name || !cluster_needed {
+            delete_if_exists(
+                kube::Api::<ClusterRole>::all(ctx.client.clone()),
+                &role.name_any(),
+            )
+            .await?;
+        }
+    }
+    Ok(())
+}
+
+/// Grant permissions to the subjects.
+///
+/// Creates a role and a role binding in every namespace and a cluster role
+/// with a cluster role binding, all named `name` and having the given metadata.
+/// Objects which have the label and are no longer needed are removed,
+/// so the metadata must contain the label.
+///
+/// Namespaces which don't exist yet or where the objects cannot be applied
+/// are reported in the result and don't block the rest of the permissions.
+#[allow(clippy::missing_panics_doc, clippy::too_many_lines)]
+pub async fn apply_permissions(
+    ctx: &OperatorCtx,
+    metadata: &ObjectMeta,
+    label: (&str, &str),
+    namespaced: &[NamespacedPermissions],
+    cluster: &[Permission],
+    subjects: &[Subject],
+) -> KuoResult<AppliedPermissions> {
+    let mut applied = AppliedPermissions::default();
+    let force = ctx.args.force_conflicts;
+    let mut metadata = metadata.clone();
+    metadata.insert_label(MANAGED_BY_LABEL, MANAGED_BY);
+    // SAFETY: Caller always sets the name.
+    let name = metadata.name.clone().unwrap();
+    let role_ref = |kind: &str| RoleRef {
+        api_group: String::from(Role::GROUP),
+        kind: String::from(kind),
+        name: name.clone(),
+    };
+    let mut rules = BTreeMap::<String, Vec<PolicyRule>>::new();
+    for permissions in namespaced {
+        for namespace in permissions
+            .namespaces(&ctx.client, &ctx.cache.namespaces)
+            .await?
+        {
+            rules.entry(namespace).or_default().extend(
+                permissions
+                    .permissions
+                    .iter()
+                    .cloned()
+                    .map(PolicyRule::from),
+            );
+        }
+    }
+    for (namespace, rules) in &rules {
+        let metadata = ObjectMeta {
+            namespace: Some(namespace.clone()),
+            ..metadata.clone()
+        };
+        let res = async {
+            Role {
+                metadata: metadata.clone(),
+                rules: Some(rules.clone()),
+            }
+            .patch_or_create_cached(
+                kube::Api::namespaced(ctx.client.clone(), namespace),
+                &ctx.cache.roles,
+                force,
+            )
+            .await?
+            .record(&mut applied.reverted);
+            RoleBinding {
+                metadata,
+                role_ref: role_ref(Role::KIND),
+                subjects: Some(subjects.to_vec()),
+            }
+            .patch_or_create_cached(
+                kube::Api::namespaced(ctx.client.clone(), namespace),
+                &ctx.cache.role_bindings,
+                force,
+            )
+            .await?
+            .record(&mut applied.reverted);
+            KuoResult::Ok(())
+        }
+        .await;
+        match res {
+            Ok(()) => applied.roles.push(format!("{namespace}/{name}")),
+            Err(err) if err.is_not_found() => {
+                tracing::info!(
+                    "Namespace {namespace} doesn't exist yet. \
+                     Permissions will be applied once it's created."
+                );
+                applied.pending_namespaces.push(namespace.clone());
+            }
+            Err(err) => {
+                tracing::warn!("Failed to apply permissions in namespace {namespace}. {err}");
+                applied.failed_namespaces.push(namespace.clone());
+            }
+        }
+    }
+    if !cluster.is_empty() {
+        ClusterRole {
+            metadata: metadata.clone(),
+            rules: Some(cluster.iter().cloned().map(PolicyRule::from).collect()),
+            ..Default::default()
+        }
+        .patch_or_create_cached(
+            kube::Api::all(ctx.client.clone()),
+            &ctx.cache.cluster_roles,
+            force,
+        )
+        .await?
+        .record(&mut applied.reverted);
+        ClusterRoleBinding {
+            metadata: metadata.clone(),
+            role_ref: role_ref(ClusterRole::KIND),
+            subjects: Some(subjects.to_vec()),
+        }
+        .patch_or_create_cached(
+            kube::Api::all(ctx.client.clone()),
+            &ctx.cache.cluster_role_bindings,
+            force,
+        )
+        .await?
+        .record(&mut applied.reverted);
+        applied.cluster_roles.push(name.clone());
+    }
+
+    let known_namespaces = rules.keys().cloned().collect::<BTreeSet<_>>();
+    remove_unneeded(ctx, label, &name, &known_namespaces, !cluster.is_empty()).await?;
+    Ok(applied)
+}
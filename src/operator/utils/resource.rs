@@ -1,15 +1,114 @@
-use crate::operator::error::KuoResult;
+use std::sync::Arc;
+
+use crate::operator::{
+    cache::{MANAGED_BY, MANAGED_BY_LABEL},
+    error::{KuoError, KuoResult},
+    utils::digest::digest,
+};
+use k8s_openapi::ClusterResourceScope;
 use kube::{
-    api::{PatchParams, PostParams},
+    api::{ObjectMeta, Patch, PatchParams},
+    core::object::HasStatus,
+    runtime::reflector::{ObjectRef, Store},
     ResourceExt,
 };
 use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+
+/// Field manager used for server-side apply.
+pub const FIELD_MANAGER: &str = "kuo-operator";
+
+/// Annotation with the digest of the object as it was last applied by the operator.
+pub const SPEC_HASH_ANNOTATION: &str = "kuo.github.io/spec-hash";
+
+/// Check whether the value is `null`, an empty list or an empty object.
+///
+/// The API server may omit such fields in the live object.
+fn is_empty(value: &Value) -> bool {
+    match value {
+        Value::Null => true,
+        Value::Array(values) => values.is_empty(),
+        Value::Object(fields) => fields.is_empty(),
+        _ => false,
+    }
+}
+
+/// Check whether all fields set in `desired` have the same values in `live`.
+///
+/// Lists must have the same length, `null` values match any value,
+/// empty lists and objects match missing fields.
+fn is_subset(desired: &Value, live: &Value) -> bool {
+    match (desired, live) {
+        (Value::Object(desired), Value::Object(live)) => desired.iter().all(|(key, value)| {
+            value.is_null()
+                || live
+                    .get(key)
+                    .filter(|live| !live.is_null())
+                    .map_or_else(|| is_empty(value), |live| is_subset(value, live))
+        }),
+        (Value::Array(desired), Value::Array(live)) => {
+            desired.len() == live.len()
+                && desired
+                    .iter()
+                    .zip(live)
+                    .all(|(desired, live)| is_subset(desired, live))
+        }
+        _ => desired == live,
+    }
+}
+
+/// Check whether the object has been created by the operator.
+///
+/// Objects created by older releases may lack the managed-by label,
+/// but they are still owned by one of the operator's resources.
+fn is_operator_owned(meta: &ObjectMeta) -> bool {
+    let labelled = meta
+        .labels
+        .as_ref()
+        .and_then(|labels| labels.get(MANAGED_BY_LABEL))
+        .is_some_and(|value| value == MANAGED_BY);
+    labelled
+        || meta
+            .owner_references
+            .iter()
+            .flatten()
+            .any(|owner| owner.api_version.starts_with("kuo.github.io/"))
+}
 
-pub(crate) trait KuoResourceExt<T>: kube::Resource + Sized {
-    async fn patch_or_create(&self, api: kube::Api<T>) -> KuoResult<Self>;
+/// Object applied by the operator.
+pub struct Applied<K> {
+    /// Live object after the apply.
+    pub object: K,
+    /// Whether changes made to the object by someone else have been reverted.
+    pub reverted: bool,
 }
 
-impl<K> KuoResourceExt<K> for K
+impl<K: kube::Resource<DynamicType = ()>> Applied<K> {
+    /// Add the object to the list of reverted objects if its changes have been reverted.
+    ///
+    /// Objects are recorded as `{kind} {namespace}/{name}`.
+    pub fn record(self, reverted: &mut Vec<String>) -> K {
+        if self.reverted {
+            let name = self.object.name_any();
+            let name = self
+                .object
+                .namespace()
+                .map_or_else(|| name.clone(), |namespace| format!("{namespace}/{name}"));
+            reverted.push(format!("{} {name}", K::kind(&())));
+        }
+        self.object
+    }
+}
+
+/// Apply the object with server-side apply unless the live object is up to date.
+///
+/// If the live object has been applied with the same digest, but doesn't contain
+/// the desired fields anymore, it has been modified by someone else.
+/// Such changes are reverted even if `force` isn't set.
+///
+/// Conflicts in objects created by the operator are resolved by applying the object again
+/// with force, because older releases wrote them under another field manager.
+async fn apply<K>(obj: &K, api: kube::Api<K>, live: Option<K>, force: bool) -> KuoResult<Applied<K>>
 where
     K: kube::Resource<DynamicType = ()>
         + DeserializeOwned
@@ -19,18 +118,178 @@ where
         + Sync
         + Send,
 {
-    async fn patch_or_create(&self, api: kube::Api<K>) -> KuoResult<Self> {
-        let meta = api.get_metadata_opt(self.name_any().as_str()).await?;
-        let new_obj = if meta.is_none() {
-            api.create(&PostParams::default(), self).await?
-        } else {
-            api.patch(
-                self.name_any().as_str(),
-                &PatchParams::default(),
-                &kube::api::Patch::Merge(self),
-            )
-            .await?
-        };
-        Ok(new_obj)
+    let name = obj.name_any();
+    let hash = digest(obj)?;
+    let mut desired = obj.clone();
+    desired
+        .annotations_mut()
+        .insert(String::from(SPEC_HASH_ANNOTATION), hash.clone());
+    let mut reverted = false;
+    if let Some(live) = &live {
+        if live.annotations().get(SPEC_HASH_ANNOTATION) == Some(&hash) {
+            if is_subset(
+                &serde_json::to_value(&desired)?,
+                &serde_json::to_value(live)?,
+            ) {
+                tracing::debug!("{name} is up to date");
+                return Ok(Applied {
+                    object: live.clone(),
+                    reverted: false,
+                });
+            }
+            tracing::info!("{name} has been modified. Reverting the changes.");
+            reverted = true;
+        }
+    }
+    let mut params = PatchParams::apply(FIELD_MANAGER);
+    params.force = force || reverted;
+    let err = match api.patch(&name, &params, &Patch::Apply(&desired)).await {
+        Ok(object) => return Ok(Applied { object, reverted }),
+        Err(kube::Error::Api(err)) if err.code == 409 => err,
+        Err(err) => return Err(err.into()),
+    };
+    let owner = match live {
+        Some(live) => Some(live.meta().clone()),
+        None => api.get_metadata_opt(&name).await?.map(|meta| meta.metadata),
+    };
+    if params.force || !owner.as_ref().is_some_and(is_operator_owned) {
+        return Err(KuoError::FieldConflict(err.message));
+    }
+    tracing::info!("Taking over fields of {name} from other field managers");
+    params.force = true;
+    let object = api.patch(&name, &params, &Patch::Apply(&desired)).await?;
+    Ok(Applied { object, reverted })
+}
+
+/// Patch the status of the object if it differs from the `previous` one.
+///
+/// Reconcilers modify the status of the object in place
+/// and save it with a single patch at the end of the reconciliation.
+pub async fn patch_status<K>(
+    client: &kube::Client,
+    obj: &K,
+    previous: Option<&K::Status>,
+) -> KuoResult<()>
+where
+    K: kube::Resource<DynamicType = (), Scope = ClusterResourceScope>
+        + HasStatus
+        + DeserializeOwned
+        + Clone
+        + std::fmt::Debug
+        + Sync,
+    K::Status: Serialize + PartialEq + Sync,
+{
+    if obj.status() == previous {
+        return Ok(());
+    }
+    kube::Api::<K>::all(client.clone())
+        .patch_status(
+            &obj.name_any(),
+            &PatchParams::default(),
+            &Patch::Merge(serde_json::json!({ "status": obj.status() })),
+        )
+        .await?;
+    Ok(())
+}
+
+pub(crate) trait KuoResourceExt:
+    kube::Resource<DynamicType = ()> + Clone + Sized + 'static
+{
+    /// Create or update the object with server-side apply.
+    ///
+    /// Fields previously applied by the operator and missing in the object are removed.
+    /// Fields owned by other managers are taken over if `force` is set
+    /// or the object has been created by the operator,
+    /// otherwise such conflicts fail with [`KuoError::FieldConflict`].
+    ///
+    /// The digest of the object is stored in the `kuo.github.io/spec-hash` annotation.
+    async fn patch_or_create(&self, api: kube::Api<Self>, force: bool) -> KuoResult<Self>;
+
+    /// Same as [`KuoResourceExt::patch_or_create`],
+    /// but the live object is taken from the cache.
+    /// The write is skipped if the live object has the same digest
+    /// and still contains all fields of the object.
+    ///
+    /// The object must have the namespace set if it's namespaced.
+    /// The result tells whether changes made by someone else have been reverted.
+    async fn patch_or_create_cached(
+        &self,
+        api: kube::Api<Self>,
+        cache: &Store<Self>,
+        force: bool,
+    ) -> KuoResult<Applied<Self>>;
+}
+
+impl<K> KuoResourceExt for K
+where
+    K: kube::Resource<DynamicType = ()>
+        + DeserializeOwned
+        + Serialize
+        + Clone
+        + std::fmt::Debug
+        + Sync
+        + Send
+        + 'static,
+{
+    async fn patch_or_create(&self, api: kube::Api<K>, force: bool) -> KuoResult<Self> {
+        Ok(apply(self, api, None, force).await?.object)
+    }
+
+    async fn patch_or_create_cached(
+        &self,
+        api: kube::Api<K>,
+        cache: &Store<K>,
+        force: bool,
+    ) -> KuoResult<Applied<Self>> {
+        let mut key = ObjectRef::new(&self.name_any());
+        if let Some(namespace) = self.namespace() {
+            key = key.within(&namespace);
+        }
+        let live = cache.get(&key).map(Arc::unwrap_or_clone);
+        apply(self, api, live, force).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::is_subset;
+
+    #[test]
+    fn is_subset_table() {
+        let cases = [
+            (json!({}), json!({}), true),
+            (json!({"a": 1}), json!({"a": 1, "b": 2}), true),
+            (json!({"a": 1}), json!({"a": 2}), false),
+            (json!({"a": 1}), json!({}), false),
+            (json!({"a": null}), json!({}), true),
+            (json!({"a": null}), json!({"a": 1}), true),
+            (json!({"a": []}), json!({}), true),
+            (json!({"a": []}), json!({"a": null}), true),
+            (json!({"a": []}), json!({"a": [1]}), false),
+            (json!({"a": {}}), json!({}), true),
+            (json!({"a": {}}), json!({"a": {"b": 1}}), true),
+            (json!({"a": {"b": []}}), json!({"a": {}}), true),
+            (json!({"a": [1]}), json!({}), false),
+            (json!({"a": {"b": 1}}), json!({}), false),
+            (json!({"a": [1, 2]}), json!({"a": [1, 2]}), true),
+            (json!({"a": [1, 2]}), json!({"a": [2, 1]}), false),
+            (json!({"a": [1]}), json!({"a": [1, 2]}), false),
+            (
+                json!({"a": [{"b": 1}]}),
+                json!({"a": [{"b": 1, "c": 2}]}),
+                true,
+            ),
+            (json!({"a": [{"b": []}]}), json!({"a": [{}]}), true),
+            (json!({"a": "1"}), json!({"a": 1}), false),
+        ];
+        for (desired, live, expected) in cases {
+            assert_eq!(
+                is_subset(&desired, &live),
+                expected,
+                "is_subset({desired}, {live})"
+            );
+        }
     }
 }